@@ -0,0 +1,246 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::config::{Config, SolanaCluster};
+use crate::health::Health;
+use crate::source::{FailoverSource, InstrumentedSource, RateLimiter, ResilientSource};
+use crate::storage::{BoundedStorage, MemoryDatabase, SqliteDatabase, Storage};
+use crate::types::{DataAggregator, Retrieval, RetrievalError};
+
+/// Upstream providers, cache and health of one Solana cluster
+pub struct ClusterContext {
+    pub cluster: SolanaCluster,
+    // Requests without a cluster prefix or query parameter are served by the default cluster
+    pub default: bool,
+    pub aggregator: DataAggregator,
+    pub health: Arc<Health>,
+    pub providers: Arc<FailoverSource>,
+}
+
+impl ClusterContext {
+    fn new(
+        config: &Config,
+        cluster: SolanaCluster,
+        limiter: Arc<RateLimiter>,
+    ) -> Result<Self, anyhow::Error> {
+        let health = Arc::new(Health::new(&config.health, config.monitor.interval_secs));
+        // The limiter wraps every Helius provider, so each request sent to Helius, failovers
+        // included, is throttled and charged
+        let providers = Arc::new(FailoverSource::from_config(
+            &config.provider_configs(cluster),
+            &config.helius_for(cluster),
+            &limiter,
+        )?);
+        // Retries wrap the instrumentation, so every attempt is counted and feeds the health
+        let source = Arc::new(ResilientSource::new(
+            Arc::new(InstrumentedSource::new(providers.clone(), health.clone())),
+            &config.upstream,
+            cluster.as_str(),
+        ));
+
+        // The cache is kept in memory unless an SQLite database path is provided
+        let database: Box<dyn Storage> = match config.sqlite_path(cluster) {
+            Some(path) => Box::new(SqliteDatabase::open(path)?),
+            None => Box::new(MemoryDatabase::new()),
+        };
+        // Capacity limits and eviction are applied on top of either backend
+        let database = BoundedStorage::new(database, &config.storage)?;
+        let aggregator = DataAggregator::new(
+            Retrieval::with_database(source, Box::new(database))
+                .with_stale_fallback(config.upstream.serve_stale),
+        );
+
+        Ok(ClusterContext {
+            cluster,
+            default: cluster == config.helius.cluster,
+            aggregator,
+            health,
+            providers,
+        })
+    }
+
+    /// Background task names are prefixed with the cluster, except for the default cluster
+    pub fn task_name(&self, task: &str) -> String {
+        if self.default {
+            task.to_string()
+        } else {
+            format!("{}/{}", self.cluster.as_str(), task)
+        }
+    }
+}
+
+/// Every cluster served by the process, the default one first.
+/// The rate limit and the credit budget are shared by all of them.
+pub struct Clusters {
+    clusters: Vec<Arc<ClusterContext>>,
+}
+
+impl Clusters {
+    pub fn new(config: &Config) -> Result<Self, anyhow::Error> {
+        let limiter = Arc::new(RateLimiter::new(&config.rate_limit));
+        let clusters = config
+            .clusters()
+            .into_iter()
+            .map(|cluster| ClusterContext::new(config, cluster, limiter.clone()).map(Arc::new))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Clusters { clusters })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<ClusterContext>> {
+        self.clusters.iter()
+    }
+
+    pub fn get(&self, cluster: SolanaCluster) -> Option<&Arc<ClusterContext>> {
+        self.clusters
+            .iter()
+            .find(|context| context.cluster == cluster)
+    }
+
+    pub fn default_cluster(&self) -> &Arc<ClusterContext> {
+        // Config::clusters always starts with helius.cluster
+        &self.clusters[0]
+    }
+}
+
+/// Cluster of the path prefix, e.g. /devnet/account/:account_id, added by the nested routers
+#[derive(Debug, Clone, Copy)]
+pub struct PathCluster(pub SolanaCluster);
+
+#[derive(Deserialize)]
+struct ClusterQuery {
+    cluster: Option<SolanaCluster>,
+}
+
+/// Cluster a request is served by: the path prefix, else the `cluster` query parameter,
+/// else the default cluster
+pub struct SelectedCluster(pub Arc<ClusterContext>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SelectedCluster {
+    type Rejection = RetrievalError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let clusters = parts
+            .extensions
+            .get::<Arc<Clusters>>()
+            .cloned()
+            .expect("Clusters extension is added by run_axum_serve");
+
+        let cluster = match parts.extensions.get::<PathCluster>() {
+            Some(PathCluster(cluster)) => Some(*cluster),
+            None => {
+                Query::<ClusterQuery>::try_from_uri(&parts.uri)
+                    .map_err(|rejection| RetrievalError::InvalidInput(rejection.body_text()))?
+                    .0
+                    .cluster
+            }
+        };
+
+        match cluster {
+            Some(cluster) => clusters
+                .get(cluster)
+                .cloned()
+                .map(SelectedCluster)
+                .ok_or_else(|| {
+                    RetrievalError::NotFound(format!("Cluster {} is not served", cluster.as_str()))
+                }),
+            None => Ok(SelectedCluster(clusters.default_cluster().clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod cluster_tests {
+    use super::*;
+    use crate::config::{HealthConfig, ProviderKind};
+    use crate::source::{failover::Provider, mock::MockSource};
+    use axum::http::Request;
+
+    fn mock_cluster(cluster: SolanaCluster, default: bool) -> Arc<ClusterContext> {
+        let source = Arc::new(MockSource::new());
+        Arc::new(ClusterContext {
+            cluster,
+            default,
+            aggregator: DataAggregator::new(Retrieval::new(source.clone())),
+            health: Arc::new(Health::new(&HealthConfig::default(), 6)),
+            providers: Arc::new(FailoverSource::new(vec![Provider::new(
+                "mock".to_string(),
+                ProviderKind::Helius,
+                1,
+                source,
+            )])),
+        })
+    }
+
+    async fn select(
+        clusters: &Arc<Clusters>,
+        uri: &str,
+        prefix: Option<SolanaCluster>,
+    ) -> Result<SolanaCluster, RetrievalError> {
+        let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+        parts.extensions.insert(clusters.clone());
+        if let Some(cluster) = prefix {
+            parts.extensions.insert(PathCluster(cluster));
+        }
+
+        SelectedCluster::from_request_parts(&mut parts, &())
+            .await
+            .map(|SelectedCluster(context)| context.cluster)
+    }
+
+    #[tokio::test]
+    async fn select_cluster_by_prefix_or_query() {
+        let clusters = Arc::new(Clusters {
+            clusters: vec![mock_cluster(SolanaCluster::MainnetBeta, true)],
+        });
+        let account = "/account/GATaRyQr7hq52GQWq3TsCditpNhkgq5ad4EM14JoRMLu";
+
+        assert_eq!(
+            select(&clusters, account, None).await,
+            Ok(SolanaCluster::MainnetBeta)
+        );
+        let error = select(&clusters, &format!("{}?cluster=devnet", account), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "not_found");
+
+        let clusters = Arc::new(Clusters {
+            clusters: vec![
+                mock_cluster(SolanaCluster::MainnetBeta, true),
+                mock_cluster(SolanaCluster::Devnet, false),
+            ],
+        });
+        assert_eq!(
+            select(
+                &clusters,
+                &format!("{}?max_age=5&cluster=devnet", account),
+                None
+            )
+            .await,
+            Ok(SolanaCluster::Devnet)
+        );
+        assert_eq!(
+            select(&clusters, account, Some(SolanaCluster::Devnet)).await,
+            Ok(SolanaCluster::Devnet)
+        );
+        let error = select(&clusters, &format!("{}?cluster=testnet", account), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "invalid_input");
+
+        assert_eq!(clusters.default_cluster().task_name("monitor"), "monitor");
+        assert_eq!(
+            clusters
+                .get(SolanaCluster::Devnet)
+                .unwrap()
+                .task_name("monitor"),
+            "devnet/monitor"
+        );
+    }
+}
@@ -0,0 +1,1047 @@
+use clap::{Parser, ValueEnum};
+use helius::types::Cluster;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+// Configuration file picked up from the working directory when no path is given
+const DEFAULT_CONFIG_PATH: &str = "data_aggregator.toml";
+// ChainSource methods that rate limits and credit costs can be configured for
+pub const UPSTREAM_METHODS: [&str; 6] = [
+    "get_account",
+    "get_multiple_accounts",
+    "parse_transactions",
+    "get_signatures_for_address",
+    "get_slot",
+    "get_block",
+];
+
+/// Command line flags, every flag can also be set with its environment variable.
+/// Precedence: CLI flag > environment variable > configuration file > default.
+#[derive(Debug, Parser)]
+#[command(version, about = "Solana Data Aggregator")]
+pub struct Cli {
+    /// Path to the TOML configuration file
+    #[arg(long, env = "DATA_AGGREGATOR_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address the HTTP server binds to, e.g. 127.0.0.1:3000
+    #[arg(long, env = "DATA_AGGREGATOR_BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+    /// Timeout of a single HTTP request in seconds
+    #[arg(long, env = "DATA_AGGREGATOR_REQUEST_TIMEOUT_SECS")]
+    pub request_timeout_secs: Option<u64>,
+    /// Helius API key
+    #[arg(long, env = "DATA_AGGREGATOR_HELIUS_API_KEY", hide_env_values = true)]
+    pub helius_api_key: Option<String>,
+    /// Solana cluster to aggregate data from
+    #[arg(long, env = "DATA_AGGREGATOR_CLUSTER")]
+    pub cluster: Option<SolanaCluster>,
+    /// Time in seconds given to in-flight work to finish on shutdown
+    #[arg(long, env = "DATA_AGGREGATOR_SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: Option<u64>,
+    /// Interval of the server status log in seconds
+    #[arg(long, env = "DATA_AGGREGATOR_LOG_INTERVAL_SECS")]
+    pub log_interval_secs: Option<u64>,
+    /// Interval of the tracked accounts refresh in seconds
+    #[arg(long, env = "DATA_AGGREGATOR_MONITOR_INTERVAL_SECS")]
+    pub monitor_interval_secs: Option<u64>,
+    /// How tracked accounts are kept up to date
+    #[arg(long, env = "DATA_AGGREGATOR_MONITOR_MODE")]
+    pub monitor_mode: Option<MonitorMode>,
+    /// Enables the block crawler
+    #[arg(long, env = "DATA_AGGREGATOR_CRAWLER_ENABLED")]
+    pub crawler_enabled: Option<bool>,
+    /// First slot crawled when there is no checkpoint yet
+    #[arg(long, env = "DATA_AGGREGATOR_CRAWLER_START_SLOT")]
+    pub crawler_start_slot: Option<u64>,
+    /// Path to the SQLite cache database, the cache is kept in memory when not set
+    #[arg(long, env = "DATA_AGGREGATOR_SQLITE_PATH")]
+    pub sqlite_path: Option<PathBuf>,
+    /// Log level filter, e.g. info or data_aggregator=debug,warn
+    #[arg(long, env = "DATA_AGGREGATOR_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Log output format
+    #[arg(long, env = "DATA_AGGREGATOR_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub helius: HeliusConfig,
+    pub monitor: MonitorConfig,
+    pub crawler: CrawlerConfig,
+    pub storage: StorageConfig,
+    pub log: LogConfig,
+    pub health: HealthConfig,
+    pub upstream: UpstreamConfig,
+    pub rate_limit: RateLimitConfig,
+    pub clusters: ClustersConfig,
+    // Upstream providers in failover order, a single Helius provider per cluster when empty
+    pub providers: Vec<ProviderConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub request_timeout_secs: u64,
+    pub log_interval_secs: u64,
+    // Time given to in-flight requests and background tasks to finish on SIGINT or SIGTERM
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "127.0.0.1:3000".to_string(),
+            request_timeout_secs: 10,
+            log_interval_secs: 3,
+            shutdown_timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeliusConfig {
+    pub api_key: String,
+    pub cluster: SolanaCluster,
+}
+
+impl HeliusConfig {
+    fn host(&self) -> &'static str {
+        match self.cluster {
+            SolanaCluster::MainnetBeta => "mainnet.helius-rpc.com",
+            SolanaCluster::Devnet => "devnet.helius-rpc.com",
+        }
+    }
+
+    pub fn rpc_url(&self) -> String {
+        format!("https://{}/?api-key={}", self.host(), self.api_key)
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("wss://{}/?api-key={}", self.host(), self.api_key)
+    }
+}
+
+impl Default for HeliusConfig {
+    fn default() -> Self {
+        Self {
+            // Set in the file, DATA_AGGREGATOR_HELIUS_API_KEY or --helius-api-key
+            api_key: String::new(),
+            cluster: SolanaCluster::MainnetBeta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonitorConfig {
+    pub mode: MonitorMode,
+    // WebSocket endpoint used in push mode, defaults to the Helius endpoint of the cluster
+    pub ws_url: Option<String>,
+    pub interval_secs: u64,
+    // Number of getMultipleAccounts batches (up to 100 accounts each) fetched at once
+    pub max_concurrent_batches: usize,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            mode: MonitorMode::Poll,
+            ws_url: None,
+            interval_secs: 6,
+            max_concurrent_batches: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig {
+    pub enabled: bool,
+    // First slot crawled when there is no checkpoint yet, defaults to the latest slot
+    pub start_slot: Option<u64>,
+    pub interval_secs: u64,
+    // Upper limit of blocks fetched in a single crawler cycle
+    pub max_slots_per_cycle: u64,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start_slot: None,
+            interval_secs: 1,
+            max_slots_per_cycle: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MonitorMode {
+    // Refresh tracked accounts every monitor interval
+    Poll,
+    // Subscribe to account changes over WebSocket, polling only while the socket is down
+    Push,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub sqlite_path: Option<PathBuf>,
+    // Cache limits, the least recently used entries are evicted first. No limit when not set.
+    pub max_accounts: Option<usize>,
+    pub max_transactions: Option<usize>,
+    // Approximate size of the cached accounts and transactions
+    pub max_bytes: Option<usize>,
+    // Entries not requested for this long are evicted
+    pub ttl_secs: Option<u64>,
+    // Accounts that are never evicted
+    pub watched_accounts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    // The server is not ready when the monitor has not refreshed accounts for this long,
+    // defaults to 5 monitor intervals
+    pub monitor_stall_secs: Option<u64>,
+    // The server is not ready after this many upstream calls failed in a row
+    pub max_upstream_failures: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            monitor_stall_secs: None,
+            max_upstream_failures: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    // Retries of upstream calls that timed out, were rate limited or found the provider down
+    pub max_retries: u32,
+    // Delay before the first retry, doubled up to retry_max_backoff_ms with random jitter
+    pub retry_initial_backoff_ms: u64,
+    pub retry_max_backoff_ms: u64,
+    // The circuit breaker opens after this many failed upstream calls in a row ...
+    pub breaker_failure_threshold: u32,
+    // ... and fails calls fast for this long before letting a probe call through
+    pub breaker_open_secs: u64,
+    // Serve the cached account, however old, when the upstream cannot refresh it
+    pub serve_stale: bool,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            retry_initial_backoff_ms: 100,
+            retry_max_backoff_ms: 2000,
+            breaker_failure_threshold: 5,
+            breaker_open_secs: 30,
+            serve_stale: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // Upstream calls per second of every ChainSource method, bursts up to one second worth
+    pub requests_per_sec: f64,
+    // Overrides by method name, e.g. parse_transactions = 2.0
+    pub method_requests_per_sec: HashMap<String, f64>,
+    // Provider credits that can be spent per UTC day, unlimited when not set
+    pub daily_credits: Option<u64>,
+    // Credits charged per call by method name, overriding the provider defaults
+    pub method_credits: HashMap<String, u64>,
+    // Share of the rate and of the daily credits that background refreshes leave to user requests
+    pub user_reserve_percent: u8,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_sec: 10.0,
+            method_requests_per_sec: HashMap::new(),
+            daily_credits: None,
+            method_credits: HashMap::new(),
+            user_reserve_percent: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClustersConfig {
+    // Clusters served besides helius.cluster, selected by a path prefix or a query parameter
+    pub additional: Vec<SolanaCluster>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    // Unique name, shown by /admin/providers
+    pub name: String,
+    pub kind: ProviderKind,
+    // Cluster the provider serves, defaults to helius.cluster
+    #[serde(default)]
+    pub cluster: Option<SolanaCluster>,
+    // JSON-RPC endpoint of rpc providers, Helius providers are configured by [helius]
+    #[serde(default)]
+    pub url: Option<String>,
+    // Providers with a higher weight are preferred while equally healthy
+    #[serde(default = "default_provider_weight")]
+    pub weight: u32,
+}
+
+fn default_provider_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    // Helius RPC and enhanced transactions API
+    Helius,
+    // Generic Solana JSON-RPC node, without enhanced transactions
+    Rpc,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // Filter directives in the RUST_LOG syntax
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    // Human readable lines
+    Text,
+    // One JSON object per line, for log pipelines
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SolanaCluster {
+    MainnetBeta,
+    Devnet,
+}
+
+impl SolanaCluster {
+    /// Name used in the configuration and as the request path prefix
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SolanaCluster::MainnetBeta => "mainnet-beta",
+            SolanaCluster::Devnet => "devnet",
+        }
+    }
+}
+
+impl From<SolanaCluster> for Cluster {
+    fn from(cluster: SolanaCluster) -> Self {
+        match cluster {
+            SolanaCluster::MainnetBeta => Cluster::MainnetBeta,
+            SolanaCluster::Devnet => Cluster::Devnet,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read configuration file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Cannot parse configuration file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid configuration value for {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+impl Config {
+    /// The default cluster helius.cluster first, then the additional clusters
+    pub fn clusters(&self) -> Vec<SolanaCluster> {
+        std::iter::once(self.helius.cluster)
+            .chain(self.clusters.additional.iter().copied())
+            .collect()
+    }
+
+    /// Helius settings of the cluster, the API key is shared by every cluster
+    pub fn helius_for(&self, cluster: SolanaCluster) -> HeliusConfig {
+        HeliusConfig {
+            cluster,
+            ..self.helius.clone()
+        }
+    }
+
+    /// SQLite database of the cluster. Additional clusters use their own file next to
+    /// storage.sqlite_path, e.g. cache.devnet.sqlite for cache.sqlite.
+    pub fn sqlite_path(&self, cluster: SolanaCluster) -> Option<PathBuf> {
+        let path = self.storage.sqlite_path.as_ref()?;
+        if cluster == self.helius.cluster {
+            return Some(path.clone());
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!(
+                "{}.{}.{}",
+                stem,
+                cluster.as_str(),
+                extension.to_string_lossy()
+            ),
+            None => format!("{}.{}", stem, cluster.as_str()),
+        };
+        Some(path.with_file_name(file_name))
+    }
+
+    /// Providers configured for the cluster, or a Helius provider when there is none
+    pub fn provider_configs(&self, cluster: SolanaCluster) -> Vec<ProviderConfig> {
+        let providers: Vec<ProviderConfig> = self
+            .providers
+            .iter()
+            .filter(|provider| provider.cluster.unwrap_or(self.helius.cluster) == cluster)
+            .cloned()
+            .collect();
+        if providers.is_empty() {
+            return vec![ProviderConfig {
+                name: "helius".to_string(),
+                kind: ProviderKind::Helius,
+                cluster: Some(cluster),
+                url: None,
+                weight: default_provider_weight(),
+            }];
+        }
+        providers
+    }
+
+    /// Loads the configuration from the file, environment variables and CLI flags
+    pub fn load(cli: Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        config.apply_overrides(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply_overrides(&mut self, cli: Cli) {
+        if let Some(bind_address) = cli.bind_address {
+            self.server.bind_address = bind_address;
+        }
+        if let Some(request_timeout_secs) = cli.request_timeout_secs {
+            self.server.request_timeout_secs = request_timeout_secs;
+        }
+        if let Some(shutdown_timeout_secs) = cli.shutdown_timeout_secs {
+            self.server.shutdown_timeout_secs = shutdown_timeout_secs;
+        }
+        if let Some(log_interval_secs) = cli.log_interval_secs {
+            self.server.log_interval_secs = log_interval_secs;
+        }
+        if let Some(api_key) = cli.helius_api_key {
+            self.helius.api_key = api_key;
+        }
+        if let Some(cluster) = cli.cluster {
+            self.helius.cluster = cluster;
+        }
+        if let Some(interval_secs) = cli.monitor_interval_secs {
+            self.monitor.interval_secs = interval_secs;
+        }
+        if let Some(mode) = cli.monitor_mode {
+            self.monitor.mode = mode;
+        }
+        if let Some(enabled) = cli.crawler_enabled {
+            self.crawler.enabled = enabled;
+        }
+        if let Some(start_slot) = cli.crawler_start_slot {
+            self.crawler.start_slot = Some(start_slot);
+        }
+        if let Some(sqlite_path) = cli.sqlite_path {
+            self.storage.sqlite_path = Some(sqlite_path);
+        }
+        if let Some(level) = cli.log_level {
+            self.log.level = level;
+        }
+        if let Some(format) = cli.log_format {
+            self.log.format = format;
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.server
+            .bind_address
+            .parse::<SocketAddr>()
+            .map_err(|error| ConfigError::Invalid {
+                field: "server.bind_address",
+                reason: format!(
+                    "{:?} is not a socket address ({})",
+                    self.server.bind_address, error
+                ),
+            })?;
+
+        for (field, value) in [
+            (
+                "server.request_timeout_secs",
+                self.server.request_timeout_secs,
+            ),
+            ("server.log_interval_secs", self.server.log_interval_secs),
+            (
+                "server.shutdown_timeout_secs",
+                self.server.shutdown_timeout_secs,
+            ),
+            ("monitor.interval_secs", self.monitor.interval_secs),
+            (
+                "monitor.max_concurrent_batches",
+                self.monitor.max_concurrent_batches as u64,
+            ),
+            ("crawler.interval_secs", self.crawler.interval_secs),
+            (
+                "crawler.max_slots_per_cycle",
+                self.crawler.max_slots_per_cycle,
+            ),
+            (
+                "health.max_upstream_failures",
+                self.health.max_upstream_failures as u64,
+            ),
+            (
+                "upstream.retry_initial_backoff_ms",
+                self.upstream.retry_initial_backoff_ms,
+            ),
+            (
+                "upstream.breaker_failure_threshold",
+                self.upstream.breaker_failure_threshold as u64,
+            ),
+            (
+                "upstream.breaker_open_secs",
+                self.upstream.breaker_open_secs,
+            ),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Invalid {
+                    field,
+                    reason: "must be greater than 0".to_string(),
+                });
+            }
+        }
+
+        for (field, value) in [
+            ("storage.max_accounts", self.storage.max_accounts),
+            ("storage.max_transactions", self.storage.max_transactions),
+            ("storage.max_bytes", self.storage.max_bytes),
+            (
+                "storage.ttl_secs",
+                self.storage.ttl_secs.map(|ttl_secs| ttl_secs as usize),
+            ),
+        ] {
+            if value == Some(0) {
+                return Err(ConfigError::Invalid {
+                    field,
+                    reason: "must be greater than 0 when set".to_string(),
+                });
+            }
+        }
+
+        for account_id in &self.storage.watched_accounts {
+            account_id
+                .parse::<Pubkey>()
+                .map_err(|_| ConfigError::Invalid {
+                    field: "storage.watched_accounts",
+                    reason: format!("{:?} is not an account public key", account_id),
+                })?;
+        }
+
+        if let Some(ws_url) = &self.monitor.ws_url {
+            if !ws_url.starts_with("ws://") && !ws_url.starts_with("wss://") {
+                return Err(ConfigError::Invalid {
+                    field: "monitor.ws_url",
+                    reason: format!("{:?} is not a ws:// or wss:// URL", ws_url),
+                });
+            }
+        }
+
+        if let Some(monitor_stall_secs) = self.health.monitor_stall_secs {
+            if monitor_stall_secs <= self.monitor.interval_secs {
+                return Err(ConfigError::Invalid {
+                    field: "health.monitor_stall_secs",
+                    reason: format!(
+                        "must be greater than monitor.interval_secs ({})",
+                        self.monitor.interval_secs
+                    ),
+                });
+            }
+        }
+
+        if self.upstream.retry_max_backoff_ms < self.upstream.retry_initial_backoff_ms {
+            return Err(ConfigError::Invalid {
+                field: "upstream.retry_max_backoff_ms",
+                reason: format!(
+                    "must not be less than upstream.retry_initial_backoff_ms ({})",
+                    self.upstream.retry_initial_backoff_ms
+                ),
+            });
+        }
+
+        let invalid_rate =
+            |requests_per_sec: f64| requests_per_sec.is_nan() || requests_per_sec <= 0.0;
+        if invalid_rate(self.rate_limit.requests_per_sec) {
+            return Err(ConfigError::Invalid {
+                field: "rate_limit.requests_per_sec",
+                reason: "must be greater than 0".to_string(),
+            });
+        }
+        if let Some((method, _)) = self
+            .rate_limit
+            .method_requests_per_sec
+            .iter()
+            .find(|(_, requests_per_sec)| invalid_rate(**requests_per_sec))
+        {
+            return Err(ConfigError::Invalid {
+                field: "rate_limit.method_requests_per_sec",
+                reason: format!("{} must be greater than 0", method),
+            });
+        }
+        for (field, methods) in [
+            (
+                "rate_limit.method_requests_per_sec",
+                self.rate_limit
+                    .method_requests_per_sec
+                    .keys()
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "rate_limit.method_credits",
+                self.rate_limit.method_credits.keys().collect(),
+            ),
+        ] {
+            if let Some(method) = methods
+                .into_iter()
+                .find(|method| !UPSTREAM_METHODS.contains(&method.as_str()))
+            {
+                return Err(ConfigError::Invalid {
+                    field,
+                    reason: format!(
+                        "{:?} is not an upstream method, expected one of {}",
+                        method,
+                        UPSTREAM_METHODS.join(", ")
+                    ),
+                });
+            }
+        }
+        if self.rate_limit.daily_credits == Some(0) {
+            return Err(ConfigError::Invalid {
+                field: "rate_limit.daily_credits",
+                reason: "must be greater than 0 when set".to_string(),
+            });
+        }
+        if self.rate_limit.user_reserve_percent > 100 {
+            return Err(ConfigError::Invalid {
+                field: "rate_limit.user_reserve_percent",
+                reason: "must not be greater than 100".to_string(),
+            });
+        }
+
+        let clusters = self.clusters();
+        for (index, cluster) in clusters.iter().enumerate() {
+            if clusters[..index].contains(cluster) {
+                return Err(ConfigError::Invalid {
+                    field: "clusters.additional",
+                    reason: format!(
+                        "{} is listed twice or is the default cluster helius.cluster",
+                        cluster.as_str()
+                    ),
+                });
+            }
+        }
+
+        let mut provider_names = std::collections::HashSet::new();
+        for provider in &self.providers {
+            let invalid = |reason: String| ConfigError::Invalid {
+                field: "providers",
+                reason: format!("{:?}: {}", provider.name, reason),
+            };
+            if provider.name.trim().is_empty() || !provider_names.insert(&provider.name) {
+                return Err(invalid("names must be unique and not empty".to_string()));
+            }
+            if provider.weight == 0 {
+                return Err(invalid("weight must be greater than 0".to_string()));
+            }
+            if let Some(cluster) = provider
+                .cluster
+                .filter(|cluster| !clusters.contains(cluster))
+            {
+                return Err(invalid(format!(
+                    "cluster {} is not served, see clusters.additional",
+                    cluster.as_str()
+                )));
+            }
+            match (provider.kind, &provider.url) {
+                (ProviderKind::Rpc, Some(url))
+                    if url.starts_with("http://") || url.starts_with("https://") => {}
+                (ProviderKind::Rpc, _) => {
+                    return Err(invalid(
+                        "rpc providers need an http:// or https:// url".to_string(),
+                    ))
+                }
+                (ProviderKind::Helius, Some(_)) => return Err(invalid(
+                    "helius providers are configured by the [helius] section, url is not supported"
+                        .to_string(),
+                )),
+                (ProviderKind::Helius, None) => {}
+            }
+        }
+
+        EnvFilter::try_new(&self.log.level).map_err(|error| ConfigError::Invalid {
+            field: "log.level",
+            reason: format!("{:?} is not a log filter ({})", self.log.level, error),
+        })?;
+
+        // A setup with JSON-RPC providers only does not need a Helius key
+        let uses_helius = self.clusters().into_iter().any(|cluster| {
+            self.provider_configs(cluster)
+                .iter()
+                .any(|provider| provider.kind == ProviderKind::Helius)
+        });
+        if uses_helius && self.helius.api_key.trim().is_empty() {
+            return Err(ConfigError::Invalid {
+                field: "helius.api_key",
+                reason: "must be set in the file, with DATA_AGGREGATOR_HELIUS_API_KEY \
+                    or with --helius-api-key"
+                    .to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    use crate::config::{Cli, Config, ConfigError, LogFormat, ProviderKind, SolanaCluster};
+
+    #[test]
+    fn parse_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            bind_address = "0.0.0.0:8080"
+
+            [helius]
+            api_key = "key"
+            cluster = "devnet"
+
+            [monitor]
+            interval_secs = 30
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.bind_address, "0.0.0.0:8080");
+        assert_eq!(config.server.request_timeout_secs, 10);
+        assert_eq!(config.helius.api_key, "key");
+        assert_eq!(config.helius.cluster, SolanaCluster::Devnet);
+        assert_eq!(config.monitor.interval_secs, 30);
+        assert!(config.storage.sqlite_path.is_none());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn parse_providers() {
+        let config = Config::default();
+        let providers = config.provider_configs(SolanaCluster::MainnetBeta);
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].kind, ProviderKind::Helius);
+
+        let mut config: Config = toml::from_str(
+            r#"
+            [helius]
+            api_key = "key"
+
+            [[providers]]
+            name = "helius"
+            kind = "helius"
+            weight = 3
+
+            [[providers]]
+            name = "public"
+            kind = "rpc"
+            url = "https://api.mainnet-beta.solana.com"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let providers = config.provider_configs(SolanaCluster::MainnetBeta);
+        assert_eq!(providers[0].weight, 3);
+        assert_eq!(providers[1].kind, ProviderKind::Rpc);
+        assert_eq!(providers[1].weight, 1);
+
+        config.providers[1].url = None;
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "providers",
+                ..
+            }
+        ));
+
+        config.providers[1].url = Some("https://rpc.example".to_string());
+        config.providers[1].name = "helius".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parse_clusters() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [helius]
+            api_key = "key"
+
+            [storage]
+            sqlite_path = "data/cache.sqlite"
+
+            [clusters]
+            additional = ["devnet"]
+
+            [[providers]]
+            name = "devnet-rpc"
+            kind = "rpc"
+            cluster = "devnet"
+            url = "https://api.devnet.solana.com"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.clusters(),
+            vec![SolanaCluster::MainnetBeta, SolanaCluster::Devnet]
+        );
+
+        // Each cluster has its own providers and database
+        let providers = config.provider_configs(SolanaCluster::MainnetBeta);
+        assert_eq!(providers[0].name, "helius");
+        assert_eq!(providers[0].cluster, Some(SolanaCluster::MainnetBeta));
+        let providers = config.provider_configs(SolanaCluster::Devnet);
+        assert_eq!(providers[0].name, "devnet-rpc");
+        assert_eq!(
+            config.sqlite_path(SolanaCluster::MainnetBeta),
+            Some(PathBuf::from("data/cache.sqlite"))
+        );
+        assert_eq!(
+            config.sqlite_path(SolanaCluster::Devnet),
+            Some(PathBuf::from("data/cache.devnet.sqlite"))
+        );
+
+        config.clusters.additional = vec![SolanaCluster::MainnetBeta];
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "clusters.additional",
+                ..
+            }
+        ));
+
+        // Providers of a cluster that is not served
+        config.clusters.additional.clear();
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "providers",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        let config = toml::from_str::<Config>("[server]\nbind_adress = \"0.0.0.0:8080\"");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn cli_flags_override_file() {
+        let mut config: Config = toml::from_str("[monitor]\ninterval_secs = 30").unwrap();
+        let cli = Cli::try_parse_from([
+            "data_aggregator",
+            "--monitor-interval-secs",
+            "12",
+            "--cluster",
+            "devnet",
+            "--sqlite-path",
+            "cache.sqlite",
+            "--log-format",
+            "json",
+        ])
+        .unwrap();
+
+        config.apply_overrides(cli);
+
+        assert_eq!(config.monitor.interval_secs, 12);
+        assert_eq!(config.helius.cluster, SolanaCluster::Devnet);
+        assert_eq!(
+            config.storage.sqlite_path,
+            Some(PathBuf::from("cache.sqlite"))
+        );
+        assert_eq!(config.log.format, LogFormat::Json);
+    }
+
+    fn config_with_api_key() -> Config {
+        let mut config = Config::default();
+        config.helius.api_key = "key".to_string();
+        config
+    }
+
+    #[test]
+    fn validate_config() {
+        // There is no default API key, it is only needed by Helius providers
+        let mut config = Config::default();
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "helius.api_key",
+                ..
+            }
+        ));
+        config.providers = toml::from_str::<Config>(
+            "[[providers]]\nname = \"public\"\nkind = \"rpc\"\nurl = \"https://rpc.example\"",
+        )
+        .unwrap()
+        .providers;
+        assert!(config.validate().is_ok());
+
+        config = config_with_api_key();
+        assert!(config.validate().is_ok());
+
+        config.server.bind_address = "localhost".to_string();
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "server.bind_address",
+                ..
+            }
+        ));
+
+        config = config_with_api_key();
+        config.monitor.interval_secs = 0;
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration value for monitor.interval_secs: must be greater than 0"
+        );
+
+        config = config_with_api_key();
+        config.storage.watched_accounts = vec!["not-a-pubkey".to_string()];
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "storage.watched_accounts",
+                ..
+            }
+        ));
+
+        config = config_with_api_key();
+        config.health.monitor_stall_secs = Some(config.monitor.interval_secs);
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "health.monitor_stall_secs",
+                ..
+            }
+        ));
+
+        config = config_with_api_key();
+        config.upstream.retry_max_backoff_ms = config.upstream.retry_initial_backoff_ms - 1;
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "upstream.retry_max_backoff_ms",
+                ..
+            }
+        ));
+
+        config = config_with_api_key();
+        config
+            .rate_limit
+            .method_credits
+            .insert("getAccountInfo".to_string(), 1);
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "rate_limit.method_credits",
+                ..
+            }
+        ));
+
+        config = config_with_api_key();
+        config.log.level = "data_aggregator=loud".to_string();
+        let error = config.validate().unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Invalid {
+                field: "log.level",
+                ..
+            }
+        ));
+    }
+}
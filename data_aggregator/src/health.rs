@@ -0,0 +1,182 @@
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicU32, AtomicU64, Ordering},
+    Mutex,
+};
+
+use crate::config::HealthConfig;
+use crate::types::{unix_timestamp, RetrievalError};
+
+// Monitor intervals without a refresh after which the monitor counts as stalled
+const DEFAULT_STALL_INTERVALS: u64 = 5;
+
+/// Upstream and monitor health shared by the background tasks and the readiness endpoint
+pub struct Health {
+    monitor_stall_secs: u64,
+    max_upstream_failures: u32,
+    started_at: u64,
+    // Unix timestamps, 0 when it never happened
+    last_monitor_refresh: AtomicU64,
+    last_upstream_success: AtomicU64,
+    consecutive_upstream_failures: AtomicU32,
+    last_upstream_error: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub upstream: UpstreamHealth,
+    pub monitor: MonitorHealth,
+    pub cache: CacheSizes,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpstreamHealth {
+    pub reachable: bool,
+    pub consecutive_failures: u32,
+    pub last_success_secs_ago: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MonitorHealth {
+    pub stalled: bool,
+    pub last_refresh_secs_ago: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheSizes {
+    pub accounts: usize,
+    pub transactions: usize,
+}
+
+impl Health {
+    pub fn new(config: &HealthConfig, monitor_interval_secs: u64) -> Self {
+        Health {
+            monitor_stall_secs: config
+                .monitor_stall_secs
+                .unwrap_or(monitor_interval_secs * DEFAULT_STALL_INTERVALS),
+            max_upstream_failures: config.max_upstream_failures,
+            started_at: unix_timestamp(),
+            last_monitor_refresh: AtomicU64::new(0),
+            last_upstream_success: AtomicU64::new(0),
+            consecutive_upstream_failures: AtomicU32::new(0),
+            last_upstream_error: Mutex::new(None),
+        }
+    }
+
+    pub fn record_monitor_refresh(&self) {
+        self.last_monitor_refresh
+            .store(unix_timestamp(), Ordering::SeqCst);
+    }
+
+    /// Records the outcome of an upstream call. Errors that prove the upstream answered,
+    /// e.g. an unknown account, count as a success.
+    pub fn record_upstream<T>(&self, result: &Result<T, RetrievalError>) {
+        match result {
+            // No provider was asked, the upstream is neither reachable nor down
+            Err(RetrievalError::Unsupported(_)) => {}
+            Err(error) if error.is_transient() => {
+                self.consecutive_upstream_failures
+                    .fetch_add(1, Ordering::SeqCst);
+                *self.last_upstream_error.lock().unwrap() = Some(error.to_string());
+            }
+            _ => {
+                self.consecutive_upstream_failures
+                    .store(0, Ordering::SeqCst);
+                self.last_upstream_success
+                    .store(unix_timestamp(), Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Not ready when the monitor has not refreshed for monitor_stall_secs (counted from
+    /// the start until the first refresh) or the last max_upstream_failures calls failed
+    pub fn readiness(&self, now: u64, cache: CacheSizes) -> Readiness {
+        let secs_ago = |timestamp: u64| (timestamp > 0).then(|| now.saturating_sub(timestamp));
+
+        let consecutive_failures = self.consecutive_upstream_failures.load(Ordering::SeqCst);
+        let upstream = UpstreamHealth {
+            reachable: consecutive_failures < self.max_upstream_failures,
+            consecutive_failures,
+            last_success_secs_ago: secs_ago(self.last_upstream_success.load(Ordering::SeqCst)),
+            last_error: self.last_upstream_error.lock().unwrap().clone(),
+        };
+
+        let last_refresh_secs_ago = secs_ago(self.last_monitor_refresh.load(Ordering::SeqCst));
+        let monitor = MonitorHealth {
+            stalled: last_refresh_secs_ago.unwrap_or(now.saturating_sub(self.started_at))
+                > self.monitor_stall_secs,
+            last_refresh_secs_ago,
+        };
+
+        Readiness {
+            ready: upstream.reachable && !monitor.stalled,
+            upstream,
+            monitor,
+            cache,
+        }
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::*;
+
+    fn cache_sizes() -> CacheSizes {
+        CacheSizes {
+            accounts: 0,
+            transactions: 0,
+        }
+    }
+
+    #[test]
+    fn not_ready_after_repeated_upstream_failures() {
+        let health = Health::new(&HealthConfig::default(), 6);
+        let now = unix_timestamp();
+        health.record_monitor_refresh();
+
+        for _ in 0..HealthConfig::default().max_upstream_failures {
+            assert!(health.readiness(now, cache_sizes()).ready);
+            health.record_upstream::<()>(&Err(RetrievalError::Timeout("slow".into())));
+        }
+        let readiness = health.readiness(now, cache_sizes());
+        assert!(!readiness.ready);
+        assert!(!readiness.upstream.reachable);
+        assert!(readiness.upstream.last_error.unwrap().contains("slow"));
+
+        // An unknown account is still an answer from the upstream
+        health.record_upstream::<()>(&Err(RetrievalError::NotFound("missing".into())));
+        let readiness = health.readiness(now, cache_sizes());
+        assert!(readiness.ready);
+        assert_eq!(readiness.upstream.consecutive_failures, 0);
+        assert_eq!(readiness.upstream.last_success_secs_ago, Some(0));
+    }
+
+    #[test]
+    fn not_ready_when_monitor_stalls() {
+        let health = Health::new(&HealthConfig::default(), 6);
+        let started_at = unix_timestamp();
+
+        // Before the first refresh the stall is counted from the start, 5 intervals by default
+        assert!(health.readiness(started_at, cache_sizes()).ready);
+        let readiness = health.readiness(started_at + 31, cache_sizes());
+        assert!(readiness.monitor.stalled);
+        assert!(readiness.monitor.last_refresh_secs_ago.is_none());
+
+        health.record_monitor_refresh();
+        let refreshed_at = unix_timestamp();
+        assert!(health.readiness(refreshed_at + 1, cache_sizes()).ready);
+        let readiness = health.readiness(refreshed_at + 31, cache_sizes());
+        assert!(!readiness.ready);
+        assert_eq!(readiness.monitor.last_refresh_secs_ago, Some(31));
+
+        let config = HealthConfig {
+            monitor_stall_secs: Some(60),
+            ..HealthConfig::default()
+        };
+        let health = Health::new(&config, 6);
+        health.record_monitor_refresh();
+        assert!(health.readiness(refreshed_at + 31, cache_sizes()).ready);
+    }
+}
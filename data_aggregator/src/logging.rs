@@ -0,0 +1,101 @@
+use axum::{extract::MatchedPath, http::Request};
+use tracing::{field::Empty, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogConfig, LogFormat};
+
+/// Installs the global tracing subscriber
+pub fn init(config: &LogConfig) -> Result<(), anyhow::Error> {
+    let filter = EnvFilter::try_new(&config.level)?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    }
+    .map_err(anyhow::Error::msg)
+}
+
+/// Span of a single HTTP request. Handlers and the retrieval layer fill in the empty
+/// fields through Span::current() while the request is processed.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    // Raw paths are not logged as the route, the account or signature has its own field
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched");
+
+    info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        account = Empty,
+        signature = Empty,
+        cache = Empty,
+        upstream_latency_ms = Empty,
+    )
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use super::*;
+    use axum::body::Body;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::{info, instrument::WithSubscriber, Instrument};
+
+    use crate::source::mock::MockSource;
+    use crate::types::Retrieval;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn record_request_fields_in_json() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let retrieval = Retrieval::new(Arc::new(MockSource::new()));
+        let request = Request::get("/account/unknown")
+            .body(Body::empty())
+            .unwrap();
+        // The span has to be created under the test subscriber as well
+        async {
+            async {
+                Span::current().record("account", "unknown");
+                retrieval
+                    .get_fresh_account("unknown".to_string(), None)
+                    .await
+                    .unwrap();
+                info!("Request finished");
+            }
+            .instrument(request_span(&request))
+            .await
+        }
+        .with_subscriber(subscriber)
+        .await;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["message"], "Request finished");
+        assert_eq!(line["span"]["method"], "GET");
+        assert_eq!(line["span"]["account"], "unknown");
+        assert_eq!(line["span"]["cache"], "miss");
+    }
+}
@@ -1,14 +1,22 @@
+use clap::Parser;
+use config::{Cli, Config};
 use server::run_server;
 
+mod cluster;
+mod config;
+mod health;
+mod logging;
+mod metrics;
 mod retrieval;
 mod server;
+mod source;
+mod storage;
+mod subscription;
+mod supervisor;
 mod types;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    // TODO: Currently, the pooling strategy is implemented with server_monitor.
-    // Check the possibility of a push aproach and check features of the Solana rpc,
-    // such as listening to the latest processed slot of a validator
     // TODO: Add Persistent Data Storage (optional).
     // TODO: Add more complicated test scenarios in data_aggregator_tests, for example:
     // many transactions in one test, many accounts in one test, tests with fn update_accounts().
@@ -17,7 +25,10 @@ async fn main() -> Result<(), anyhow::Error> {
     // TODO: Check again error handling and propagations with more tests.
     // TODO: Add separation between the data aggregated by the aggregator and the api consuming the data from the aggregator.
 
-    run_server().await?;
+    let config = Config::load(Cli::parse())?;
+    logging::init(&config.log)?;
+
+    run_server(config).await?;
 
     Ok(())
 }
@@ -26,9 +37,23 @@ async fn main() -> Result<(), anyhow::Error> {
 #[cfg(test)]
 mod data_aggregator_tests {
     use solana_sdk::pubkey::Pubkey;
+    use std::sync::Arc;
 
+    use crate::config::HeliusConfig;
+    use crate::source::HeliusSource;
     use crate::types::{DataAggregator, Retrieval};
 
+    fn helius_aggregator() -> DataAggregator {
+        let config = HeliusConfig {
+            api_key: std::env::var("DATA_AGGREGATOR_HELIUS_API_KEY")
+                .expect("DATA_AGGREGATOR_HELIUS_API_KEY is required by the network tests"),
+            ..HeliusConfig::default()
+        };
+        DataAggregator::new(Retrieval::new(Arc::new(
+            HeliusSource::new(&config).unwrap(),
+        )))
+    }
+
     const ACTIVE_MAINNET_ACCOUNT: &str = "BEmUSjqs7mpgaSXw6QdrePfTsD8aQHbdtnqUxa63La6E";
     const TRANSACTION_WITH_NATIVE_TRANSFERS: &str =
         "5XiFRQDYp31KxFQtJqqrjTduTZnGaEWffmv4941D34VsX2GpYavU69bpn1xwWtrcS7fE7D5KuXCjpqjQwLHHeifZ";
@@ -38,8 +63,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn fetch_active_mainnet_account() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let account = retrieval
             .fetch_account(ACTIVE_MAINNET_ACCOUNT.to_string())
@@ -54,8 +79,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn check_if_existing_account_exists() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let exists = retrieval
             .account_exists(ACTIVE_MAINNET_ACCOUNT.to_string())
@@ -77,8 +102,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_balances() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let balances = retrieval.get_account_balances().await.unwrap();
         assert_eq!(balances.len(), 0);
@@ -95,15 +120,13 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_active_mainnet_account() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let account = retrieval
             .get_account(ACTIVE_MAINNET_ACCOUNT.to_string())
             .await;
-        assert!(account.is_err());
-        let error_message = format!("{}", account.err().unwrap());
-        assert_eq!(error_message, "Account not found.");
+        assert_eq!(account.unwrap_err().code(), "not_found");
 
         retrieval
             .fetch_account(ACTIVE_MAINNET_ACCOUNT.to_string())
@@ -122,13 +145,11 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_usdc_mainnet_account() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let account = retrieval.get_account(USDC_CONTRACT.to_string()).await;
-        assert!(account.is_err());
-        let error_message = format!("{}", account.err().unwrap());
-        assert_eq!(error_message, "Account not found.");
+        assert_eq!(account.unwrap_err().code(), "not_found");
 
         retrieval
             .fetch_account(USDC_CONTRACT.to_string())
@@ -147,8 +168,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_account_count() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let account_count = retrieval.get_account_count().await.unwrap();
         assert_eq!(account_count, 0);
@@ -164,8 +185,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_transaction_count() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let transactions = retrieval.get_transaction_count().await.unwrap();
         assert_eq!(transactions, 0);
@@ -181,8 +202,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn fetch_usdc_mainnet_transaction() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let transaction = retrieval
             .fetch_transaction(USDC_TRANSACTION.to_string())
@@ -203,8 +224,8 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn check_if_existing_transaction_exists() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let exists = retrieval
             .transaction_exists(USDC_TRANSACTION.to_string())
@@ -226,15 +247,13 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_usdc_mainnet_transaction() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let transaction = retrieval
             .get_transaction(USDC_TRANSACTION.to_string())
             .await;
-        assert!(transaction.is_err());
-        let error_message = format!("{}", transaction.err().unwrap());
-        assert_eq!(error_message, "Transaction not found");
+        assert_eq!(transaction.unwrap_err().code(), "not_found");
 
         retrieval
             .fetch_transaction(USDC_TRANSACTION.to_string())
@@ -260,15 +279,13 @@ mod data_aggregator_tests {
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn get_native_transfers_mainnet_transaction() {
-        let aggregator = DataAggregator::new(Retrieval::new());
-        let mut retrieval = aggregator.retrieval.write().await;
+        let aggregator = helius_aggregator();
+        let retrieval = &aggregator.retrieval;
 
         let transaction = retrieval
             .get_transaction(TRANSACTION_WITH_NATIVE_TRANSFERS.to_string())
             .await;
-        assert!(transaction.is_err());
-        let error_message = format!("{}", transaction.err().unwrap());
-        assert_eq!(error_message, "Transaction not found");
+        assert_eq!(transaction.unwrap_err().code(), "not_found");
 
         retrieval
             .fetch_transaction(TRANSACTION_WITH_NATIVE_TRANSFERS.to_string())
@@ -0,0 +1,261 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::IntoResponse,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Process wide Prometheus metrics, rendered by the /metrics endpoint
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    // HTTP requests by matched route and status code
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    // Cache lookups by kind (account, transaction) and result (hit, miss)
+    pub cache_requests: IntCounterVec,
+    // Upstream calls by ChainSource method, errors also by RetrievalError code
+    pub upstream_requests: IntCounterVec,
+    pub upstream_errors: IntCounterVec,
+    pub upstream_request_duration: HistogramVec,
+    // Retried upstream calls by method, 1 while the circuit breaker of a cluster is open
+    pub upstream_retries: IntCounterVec,
+    pub circuit_breaker_open: IntGaugeVec,
+    // Calls a provider failed transiently before the next provider was tried
+    pub upstream_failovers: IntCounterVec,
+    // Provider credits spent by method, and during the current UTC day
+    pub upstream_credits: IntCounterVec,
+    pub upstream_credits_today: IntGauge,
+    pub monitor_cycle_duration: Histogram,
+    // Transactions of crawled blocks that could not be mapped and were not stored
+    pub dropped_block_transactions: IntCounter,
+    // Restarts of supervised background tasks by task name
+    pub task_restarts: IntCounterVec,
+    // Number of cached entries by kind
+    pub cached_entries: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["route"],
+        )
+        .unwrap();
+        let cache_requests = IntCounterVec::new(
+            Opts::new("cache_requests_total", "Cache lookups by kind and result"),
+            &["kind", "result"],
+        )
+        .unwrap();
+        let upstream_requests = IntCounterVec::new(
+            Opts::new("upstream_requests_total", "Upstream calls by method"),
+            &["method"],
+        )
+        .unwrap();
+        let upstream_errors = IntCounterVec::new(
+            Opts::new(
+                "upstream_errors_total",
+                "Failed upstream calls by method and error code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap();
+        let upstream_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Upstream call latency by method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let upstream_retries = IntCounterVec::new(
+            Opts::new("upstream_retries_total", "Retried upstream calls by method"),
+            &["method"],
+        )
+        .unwrap();
+        let circuit_breaker_open = IntGaugeVec::new(
+            Opts::new(
+                "upstream_circuit_breaker_open",
+                "1 while upstream calls of a cluster fail fast, 0 otherwise",
+            ),
+            &["cluster"],
+        )
+        .unwrap();
+        let upstream_failovers = IntCounterVec::new(
+            Opts::new(
+                "upstream_failovers_total",
+                "Calls failed over to the next provider, by failed provider",
+            ),
+            &["provider"],
+        )
+        .unwrap();
+        let upstream_credits = IntCounterVec::new(
+            Opts::new(
+                "upstream_credits_consumed_total",
+                "Provider credits spent by method",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let upstream_credits_today = IntGauge::new(
+            "upstream_credits_consumed_today",
+            "Provider credits spent during the current UTC day",
+        )
+        .unwrap();
+        let monitor_cycle_duration = Histogram::with_opts(HistogramOpts::new(
+            "monitor_cycle_duration_seconds",
+            "Duration of an account monitor refresh",
+        ))
+        .unwrap();
+        let dropped_block_transactions = IntCounter::new(
+            "crawler_dropped_transactions_total",
+            "Transactions of crawled blocks that could not be decoded",
+        )
+        .unwrap();
+        let task_restarts = IntCounterVec::new(
+            Opts::new("task_restarts_total", "Background task restarts by task"),
+            &["task"],
+        )
+        .unwrap();
+        let cached_entries = IntGaugeVec::new(
+            Opts::new(
+                "cached_entries",
+                "Number of cached entries by cluster and kind",
+            ),
+            &["cluster", "kind"],
+        )
+        .unwrap();
+
+        // Names are unique and static, registration cannot fail
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(cache_requests.clone())).unwrap();
+        registry
+            .register(Box::new(upstream_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_errors.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_retries.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(circuit_breaker_open.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_failovers.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_credits.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_credits_today.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(monitor_cycle_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(dropped_block_transactions.clone()))
+            .unwrap();
+        registry.register(Box::new(task_restarts.clone())).unwrap();
+        registry.register(Box::new(cached_entries.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            cache_requests,
+            upstream_requests,
+            upstream_errors,
+            upstream_request_duration,
+            upstream_retries,
+            circuit_breaker_open,
+            upstream_failovers,
+            upstream_credits,
+            upstream_credits_today,
+            monitor_cycle_duration,
+            dropped_block_transactions,
+            task_restarts,
+            cached_entries,
+        }
+    }
+
+    pub fn record_cache_lookup(&self, kind: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.cache_requests.with_label_values(&[kind, result]).inc();
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        // Encoding into a Vec only fails on metric families that were never registered
+        if let Err(error) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::warn!(%error, "Metrics encoding failed");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Records the count and latency of requests by their matched route
+pub async fn track_http_requests(request: Request, next: Next) -> impl IntoResponse {
+    // Raw paths are not used as labels, they carry account ids and signatures
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&route])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .http_requests
+        .with_label_values(&[&route, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+
+    #[test]
+    fn render_recorded_metrics() {
+        METRICS.record_cache_lookup("account", true);
+        METRICS.record_cache_lookup("account", false);
+        METRICS
+            .cached_entries
+            .with_label_values(&["devnet", "transaction"])
+            .set(3);
+
+        let rendered = METRICS.render();
+        assert!(rendered.contains("cache_requests_total{kind=\"account\",result=\"hit\"}"));
+        assert!(rendered.contains("cache_requests_total{kind=\"account\",result=\"miss\"}"));
+        assert!(rendered.contains("cached_entries{cluster=\"devnet\",kind=\"transaction\"} 3"));
+    }
+}
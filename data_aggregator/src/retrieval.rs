@@ -1,41 +1,76 @@
-use anyhow::Error;
-use helius::{
-    types::{Cluster, ParseTransactionsRequest},
-    Helius,
-};
+use futures::stream::{self, StreamExt};
 use indexmap::IndexMap;
-use solana_sdk::pubkey::Pubkey;
-use std::collections::HashMap;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{warn, Span};
+
+use crate::metrics::METRICS;
+use crate::source::ChainSource;
+use crate::storage::{CacheStats, Storage};
+use crate::types::{
+    Account, AccountUpdateError, CrawlReport, Retrieval, RetrievalError, Transaction,
+    TransactionLookup, TransactionPage, UpdateReport,
+};
+
+mod single_flight;
+
+pub use self::single_flight::SingleFlight;
 
-use crate::types::{Account, Database, NativeTransfer, Retrieval, Transaction};
+// Upper limit of accounts in a single getMultipleAccounts request
+const MAX_ACCOUNTS_PER_BATCH: usize = 100;
+// Upper limit of signatures in a single parse transactions request
+pub const MAX_TRANSACTIONS_PER_BATCH: usize = 100;
+// Page size of the account transaction history when no limit is requested
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+// Storage checkpoint name of the block crawler
+const CRAWLER_CHECKPOINT: &str = "crawler";
+// Attempts at a block the upstream answers with an error before its slot is skipped
+const MAX_BLOCK_ATTEMPTS: u32 = 3;
+
+// Counts the lookup and marks the current request span, see logging::request_span
+fn record_cache_lookup(kind: &str, hit: bool) {
+    METRICS.record_cache_lookup(kind, hit);
+    Span::current().record("cache", if hit { "hit" } else { "miss" });
+}
 
 impl Retrieval {
-    pub fn new() -> Self {
-        // Free Helius API key.
-        let helius = match Helius::new("24cf0798-4008-4c81-aa5e-2875323278cd", Cluster::MainnetBeta)
-        {
-            Ok(helius) => helius,
-            Err(error) => panic!("Cannot establish Helius API connection: {:?}", error),
-        };
+    // The server always bounds the cache, see run_server
+    #[cfg(test)]
+    pub fn new(source: Arc<dyn ChainSource>) -> Self {
+        Self::with_database(source, Box::new(crate::storage::MemoryDatabase::new()))
+    }
 
+    pub fn with_database(source: Arc<dyn ChainSource>, database: Box<dyn Storage>) -> Self {
         Retrieval {
-            helius,
-            database: Database {
-                accounts: HashMap::new(),
-                transactions: HashMap::new(),
-            },
+            source,
+            database,
+            account_fetches: SingleFlight::default(),
+            transaction_fetches: SingleFlight::default(),
+            serve_stale: false,
+            crawler_failures: Mutex::new((0, 0)),
         }
     }
 
-    pub async fn get_account_balances(&self) -> Result<IndexMap<String, f64>, Error> {
+    /// Serves the cached account, whatever its age, when the upstream cannot refresh it
+    pub fn with_stale_fallback(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    pub async fn get_account_balances(&self) -> Result<IndexMap<String, f64>, RetrievalError> {
         // IndexMap is here to persist order of elements in logs
         let balances: IndexMap<String, f64> = self
             .database
-            .accounts
-            .values()
+            .list_accounts()?
+            .iter()
             .map(|account| {
                 (
-                    account.account_pubkey.to_string().clone(),
+                    account.account_pubkey.to_string(),
                     account.lamports as f64 / 1_000_000_000.0,
                 )
             })
@@ -44,118 +79,1342 @@ impl Retrieval {
         Ok(balances)
     }
 
-    pub async fn get_account_count(&self) -> Result<usize, Error> {
-        Ok(self.database.accounts.len())
+    pub async fn get_cache_stats(&self) -> Result<Option<CacheStats>, RetrievalError> {
+        Ok(self.database.cache_stats()?)
+    }
+
+    /// Writes buffered cache data to durable storage before the server exits
+    pub async fn flush(&self) -> Result<(), RetrievalError> {
+        Ok(self.database.flush()?)
+    }
+
+    pub async fn get_account_count(&self) -> Result<usize, RetrievalError> {
+        Ok(self.database.account_count()?)
+    }
+
+    /// Public keys of all cached accounts, these are kept up to date by the monitor
+    pub async fn get_tracked_accounts(&self) -> Result<Vec<Pubkey>, RetrievalError> {
+        Ok(self
+            .database
+            .list_accounts()?
+            .iter()
+            .map(|account| account.account_pubkey)
+            .collect())
     }
 
-    pub async fn update_accounts(&mut self) -> Result<(), Error> {
-        let account_keys: Vec<String> = self.database.accounts.keys().cloned().collect();
+    /// Refreshes all cached accounts in batches of getMultipleAccounts requests.
+    /// Failures are reported per account instead of aborting the whole refresh.
+    pub async fn update_accounts(
+        &self,
+        max_concurrent_batches: usize,
+    ) -> Result<UpdateReport, RetrievalError> {
+        let account_pubkeys = self.get_tracked_accounts().await?;
+
+        let batches: Vec<Vec<Pubkey>> = account_pubkeys
+            .chunks(MAX_ACCOUNTS_PER_BATCH)
+            .map(<[Pubkey]>::to_vec)
+            .collect();
+
+        let source = self.source.clone();
+        let batches: Vec<(Vec<Pubkey>, _)> = stream::iter(batches)
+            .map(move |batch| {
+                let source = source.clone();
+                async move {
+                    let result = source.get_multiple_accounts(&batch).await;
+                    (batch, result)
+                }
+            })
+            .buffer_unordered(max_concurrent_batches.max(1))
+            .collect()
+            .await;
+
+        let mut report = UpdateReport::default();
+        for (batch, result) in batches {
+            // Every requested account must have an entry, otherwise the positions do not match
+            let result = result.and_then(|accounts| {
+                if accounts.len() == batch.len() {
+                    Ok(accounts)
+                } else {
+                    Err(RetrievalError::Decode(format!(
+                        "Upstream returned {} accounts for {} requested",
+                        accounts.len(),
+                        batch.len()
+                    )))
+                }
+            });
+            let accounts = match result {
+                Ok(accounts) => accounts,
+                Err(error) => {
+                    let error = error.to_string();
+                    report
+                        .failed
+                        .extend(batch.iter().map(|account_pubkey| AccountUpdateError {
+                            account_id: account_pubkey.to_string(),
+                            error: error.clone(),
+                        }));
+                    continue;
+                }
+            };
+
+            for (account_pubkey, account) in batch.iter().zip(accounts) {
+                let account_id = account_pubkey.to_string();
+                let stored = match account {
+                    Some(account) if account.account_pubkey != *account_pubkey => {
+                        Err(RetrievalError::Decode(format!(
+                            "Upstream returned account {} instead of {}",
+                            account.account_pubkey, account_id
+                        )))
+                    }
+                    // An account stored from a newer slot is kept, it is up to date anyway
+                    Some(account) => self
+                        .database
+                        .put_account(&account_id, &account)
+                        .map(|_| ())
+                        .map_err(RetrievalError::from),
+                    None => Err(RetrievalError::NotFound(format!(
+                        "Account {} not found",
+                        account_id
+                    ))),
+                };
 
-        // TODO: This implementation is based on naive assumptions and is suitable only for a small number of accounts.
-        // For production, implement a robust querying logic (get_multiple_accounts() can be used)
-        for account_id in account_keys {
-            self.fetch_account(account_id).await?;
+                match stored {
+                    Ok(()) => report.updated += 1,
+                    Err(error) => report.failed.push(AccountUpdateError {
+                        account_id,
+                        error: error.to_string(),
+                    }),
+                }
+            }
         }
 
+        Ok(report)
+    }
+
+    /// Fetches an account from the upstream and caches it.
+    /// Concurrent fetches of the same account share one upstream request.
+    /// With the stale fallback, a transient upstream failure or an exhausted credit budget
+    /// serves the cached account instead.
+    pub async fn fetch_account(&self, account_id: String) -> Result<Account, RetrievalError> {
+        match self.fetch_upstream_account(&account_id).await {
+            Err(error)
+                if self.serve_stale
+                    && (error.is_transient()
+                        || matches!(error, RetrievalError::BudgetExhausted(_))) =>
+            {
+                match self.database.get_account(&account_id)? {
+                    Some(account) => {
+                        warn!(
+                            account = %account_id,
+                            %error,
+                            age_secs = account.age_secs(),
+                            "Serving stale account"
+                        );
+                        Span::current().record("cache", "stale");
+                        Ok(account)
+                    }
+                    None => Err(error),
+                }
+            }
+            result => result,
+        }
+    }
+
+    async fn fetch_upstream_account(&self, account_id: &str) -> Result<Account, RetrievalError> {
+        self.account_fetches
+            .run(account_id, || async {
+                let account_pubkey = account_id.parse::<Pubkey>().map_err(|_| {
+                    RetrievalError::InvalidInput("Account validation failed.".to_string())
+                })?;
+                let updated_account = self.source.get_account(&account_pubkey).await?;
+                if updated_account.account_pubkey != account_pubkey {
+                    return Err(RetrievalError::Decode(format!(
+                        "Upstream returned account {} instead of {}",
+                        updated_account.account_pubkey, account_pubkey
+                    )));
+                }
+
+                // A newer account, e.g. from a subscription notification, is kept and served
+                if !self.database.put_account(account_id, &updated_account)? {
+                    if let Some(account) = self.database.get_account(account_id)? {
+                        return Ok(account);
+                    }
+                }
+
+                Ok(updated_account)
+            })
+            .await
+    }
+
+    /// Stores an account update received from the upstream, e.g. a subscription notification,
+    /// unless the cached account was read at a newer slot
+    pub async fn store_account(&self, account: Account) -> Result<(), RetrievalError> {
+        self.database
+            .put_account(&account.account_pubkey.to_string(), &account)?;
         Ok(())
     }
 
-    pub async fn fetch_account(&mut self, account_id: String) -> Result<Account, Error> {
+    /// Cached account, or None when it is not cached or older than max_age_secs
+    pub async fn get_fresh_account(
+        &self,
+        account_id: String,
+        max_age_secs: Option<u64>,
+    ) -> Result<Option<Account>, RetrievalError> {
+        let account = self
+            .database
+            .get_account(&account_id)?
+            .filter(|account| max_age_secs.is_none_or(|max_age| account.age_secs() <= max_age));
+        record_cache_lookup("account", account.is_some());
+
+        Ok(account)
+    }
+
+    // The server reads accounts with get_fresh_account
+    #[cfg(test)]
+    pub async fn get_account(&self, account_id: String) -> Result<Account, RetrievalError> {
+        match self.database.get_account(&account_id)? {
+            Some(account) => Ok(account),
+            None => Err(RetrievalError::NotFound(format!(
+                "Account {} not found",
+                account_id
+            ))),
+        }
+    }
+
+    #[cfg(test)]
+    pub async fn account_exists(&self, account_id: String) -> Result<bool, RetrievalError> {
+        Ok(self.database.account_exists(&account_id)?)
+    }
+
+    pub async fn get_transaction_count(&self) -> Result<usize, RetrievalError> {
+        Ok(self.database.transaction_count()?)
+    }
+
+    /// Fetches a transaction from the upstream and caches it.
+    /// Concurrent fetches of the same signature share one upstream request.
+    pub async fn fetch_transaction(
+        &self,
+        tx_signature: String,
+    ) -> Result<Transaction, RetrievalError> {
+        self.transaction_fetches
+            .run(&tx_signature, || async {
+                let transaction = self
+                    .source
+                    .parse_transactions(vec![tx_signature.clone()])
+                    .await?
+                    .into_iter()
+                    .find(|transaction| transaction.signature == tx_signature)
+                    .ok_or_else(|| {
+                        RetrievalError::NotFound(format!("Transaction {} not found", tx_signature))
+                    })?;
+
+                self.database
+                    .put_transaction(&transaction.signature, &transaction)?;
+
+                Ok(transaction)
+            })
+            .await
+    }
+
+    /// Looks up many transactions at once. Cached transactions are served from the storage
+    /// and the misses are fetched in a single upstream request.
+    /// Results keep the order of the signatures and carry an error per signature.
+    pub async fn fetch_transactions(
+        &self,
+        tx_signatures: &[String],
+    ) -> Result<Vec<TransactionLookup>, RetrievalError> {
+        if tx_signatures.len() > MAX_TRANSACTIONS_PER_BATCH {
+            return Err(RetrievalError::InvalidInput(format!(
+                "At most {} signatures can be looked up at once",
+                MAX_TRANSACTIONS_PER_BATCH
+            )));
+        }
+
+        let (mut found, misses) = self.get_cached_transactions(tx_signatures)?;
+
+        // An upstream failure is reported for every signature that was not cached
+        let upstream_error = self
+            .fetch_missing_transactions(misses, &mut found)
+            .await
+            .err();
+
+        Ok(tx_signatures
+            .iter()
+            .map(|tx_signature| {
+                let (transaction, error) = if Signature::from_str(tx_signature).is_err() {
+                    (
+                        None,
+                        Some(RetrievalError::InvalidInput(
+                            "Invalid transaction signature format.".to_string(),
+                        )),
+                    )
+                } else if let Some(transaction) = found.get(tx_signature) {
+                    (Some(transaction.clone()), None)
+                } else if let Some(error) = &upstream_error {
+                    (None, Some(error.clone()))
+                } else {
+                    (
+                        None,
+                        Some(RetrievalError::NotFound(format!(
+                            "Transaction {} not found",
+                            tx_signature
+                        ))),
+                    )
+                };
+
+                TransactionLookup {
+                    signature: tx_signature.clone(),
+                    transaction,
+                    error: error.as_ref().map(RetrievalError::body),
+                }
+            })
+            .collect())
+    }
+
+    /// Pages through the transactions that touched an account, newest first.
+    /// The next page starts before the last signature of a full page.
+    pub async fn get_account_transactions(
+        &self,
+        account_id: &str,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<TransactionPage, RetrievalError> {
         let account_pubkey = account_id
-            .as_str()
             .parse::<Pubkey>()
-            .expect("Account pubkey cannot be parsed.");
-        // TODO: replace helius.rpc().solana_client with async/await function
-        let account_data = self
-            .helius
-            .rpc()
-            .solana_client
-            .get_account(&account_pubkey)?;
-
-        let updated_account = Account {
-            account_pubkey,
-            owner: account_data.owner,
-            lamports: account_data.lamports,
-            executable: account_data.executable,
-            rent_epoch: account_data.rent_epoch,
+            .map_err(|_| RetrievalError::InvalidInput("Account validation failed.".to_string()))?;
+        let tx_signatures = self
+            .source
+            .get_signatures_for_address(&account_pubkey, before, until, limit)
+            .await?;
+
+        let (mut found, misses) = self.get_cached_transactions(&tx_signatures)?;
+        self.fetch_missing_transactions(misses, &mut found).await?;
+
+        let next_before = match tx_signatures.len() == limit {
+            true => tx_signatures.last().cloned(),
+            false => None,
         };
+        let transactions = tx_signatures
+            .iter()
+            .filter_map(|tx_signature| found.remove(tx_signature))
+            .collect();
 
-        self.database
-            .accounts
-            .insert(account_id, updated_account.clone());
+        Ok(TransactionPage {
+            transactions,
+            next_before,
+        })
+    }
+
+    /// Splits valid signatures into the cached transactions and the unique misses
+    fn get_cached_transactions(
+        &self,
+        tx_signatures: &[String],
+    ) -> Result<(HashMap<String, Transaction>, Vec<String>), RetrievalError> {
+        let mut found: HashMap<String, Transaction> = HashMap::new();
+        let mut misses: Vec<String> = vec![];
+        for tx_signature in tx_signatures {
+            if Signature::from_str(tx_signature).is_err()
+                || found.contains_key(tx_signature)
+                || misses.contains(tx_signature)
+            {
+                continue;
+            }
+
+            let cached = self.database.get_transaction(tx_signature)?;
+            record_cache_lookup("transaction", cached.is_some());
+            match cached {
+                Some(transaction) => {
+                    found.insert(tx_signature.clone(), transaction);
+                }
+                None => misses.push(tx_signature.clone()),
+            }
+        }
+
+        Ok((found, misses))
+    }
+
+    /// Fetches the misses in a single upstream request and caches them
+    async fn fetch_missing_transactions(
+        &self,
+        misses: Vec<String>,
+        found: &mut HashMap<String, Transaction>,
+    ) -> Result<(), RetrievalError> {
+        if misses.is_empty() {
+            return Ok(());
+        }
+
+        for transaction in self.source.parse_transactions(misses.clone()).await? {
+            // Transactions that were not requested are not cached
+            if !misses.contains(&transaction.signature) {
+                continue;
+            }
+            self.database
+                .put_transaction(&transaction.signature, &transaction)?;
+            found.insert(transaction.signature.clone(), transaction);
+        }
+
+        Ok(())
+    }
+
+    /// Cached transaction, read once so an eviction cannot happen between a lookup and a read
+    pub async fn get_cached_transaction(
+        &self,
+        tx_signature: String,
+    ) -> Result<Option<Transaction>, RetrievalError> {
+        let transaction = self.database.get_transaction(&tx_signature)?;
+        record_cache_lookup("transaction", transaction.is_some());
 
-        Ok(updated_account)
+        Ok(transaction)
     }
 
-    pub async fn get_account(&self, account_id: String) -> Result<Account, Error> {
-        match self.database.accounts.get(&account_id) {
-            Some(account) => Ok(account.clone()),
-            None => Err(Error::msg("Account not found.")),
+    // The server reads transactions with get_cached_transaction
+    #[cfg(test)]
+    pub async fn get_transaction(
+        &self,
+        tx_signature: String,
+    ) -> Result<Transaction, RetrievalError> {
+        match self.database.get_transaction(&tx_signature)? {
+            Some(transaction) => Ok(transaction),
+            None => Err(RetrievalError::NotFound(format!(
+                "Transaction {} not found",
+                tx_signature
+            ))),
         }
     }
 
-    pub async fn account_exists(&self, account_id: String) -> Result<bool, Error> {
-        Ok(self.database.accounts.contains_key(&account_id))
+    #[cfg(test)]
+    pub async fn transaction_exists(&self, tx_hash: String) -> Result<bool, RetrievalError> {
+        let exists = self.database.transaction_exists(&tx_hash)?;
+        record_cache_lookup("transaction", exists);
+
+        Ok(exists)
     }
 
-    pub async fn get_transaction_count(&self) -> Result<usize, Error> {
-        Ok(self.database.transactions.len())
+    /// Crawls blocks from the last checkpoint (or start_slot on the first run) up to the
+    /// latest finalized slot, processing at most max_slots blocks per call.
+    /// The checkpoint is persisted after every block, so the crawl resumes after a restart.
+    /// A block failing with other than a transient error MAX_BLOCK_ATTEMPTS times is skipped.
+    pub async fn update_transactions(
+        &self,
+        start_slot: Option<u64>,
+        max_slots: u64,
+    ) -> Result<CrawlReport, RetrievalError> {
+        let latest_slot = self.source.get_slot().await?;
+        let next_slot = match self.database.get_checkpoint(CRAWLER_CHECKPOINT)? {
+            Some(checkpoint) => checkpoint + 1,
+            None => start_slot.unwrap_or(latest_slot),
+        };
+
+        let mut report = CrawlReport::default();
+        let last_slot = latest_slot.min(next_slot.saturating_add(max_slots).saturating_sub(1));
+        for slot in next_slot..=last_slot {
+            match self.source.get_block(slot).await {
+                Ok(Some(transactions)) => {
+                    for transaction in &transactions {
+                        self.database
+                            .put_transaction(&transaction.signature, transaction)?;
+                    }
+                    report.transactions += transactions.len();
+                }
+                Ok(None) => report.skipped_slots += 1,
+                // The upstream is down, the block is fetched again once it is back
+                Err(error) if error.is_transient() => return Err(error),
+                Err(error) => {
+                    let attempts = self.record_block_failure(slot);
+                    if attempts < MAX_BLOCK_ATTEMPTS {
+                        return Err(error);
+                    }
+                    warn!(slot, attempts, %error, "Block skipped after repeated failures");
+                    report.failed_slots.push(slot);
+                }
+            }
+
+            self.database.put_checkpoint(CRAWLER_CHECKPOINT, slot)?;
+            report.processed_slots += 1;
+            report.last_slot = Some(slot);
+        }
+
+        Ok(report)
     }
 
-    pub async fn fetch_transaction(&mut self, tx_signature: String) -> Result<Transaction, Error> {
-        let request: ParseTransactionsRequest = ParseTransactionsRequest {
-            transactions: vec![tx_signature],
+    /// Returns the number of failed attempts at the slot, including this one
+    fn record_block_failure(&self, slot: u64) -> u32 {
+        let mut failures = self.crawler_failures.lock().unwrap();
+        *failures = match *failures {
+            (failed_slot, attempts) if failed_slot == slot => (slot, attempts + 1),
+            _ => (slot, 1),
         };
-        let tx_response = &self.helius.parse_transactions(request).await?[0];
+        failures.1
+    }
+}
 
-        let native_transfers = tx_response
-            .native_transfers
-            .as_ref()
-            .expect("Native transfers cannot be parsed.")
-            .iter()
-            .map(|native_transfer| NativeTransfer {
-                amount: native_transfer
-                    .amount
-                    .as_u64()
-                    .expect("Amount cannot be parsed."),
-                from_user_account: native_transfer.user_accounts.from_user_account.clone(),
-                to_user_account: native_transfer.user_accounts.to_user_account.clone(),
+#[cfg(test)]
+mod retrieval_tests {
+    use axum::{http::StatusCode, response::IntoResponse};
+    use serde_json::json;
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::{Duration, Instant};
+    use tokio::sync::RwLock;
+
+    use super::MAX_TRANSACTIONS_PER_BATCH;
+    use crate::source::mock::MockSource;
+    use crate::storage::SqliteDatabase;
+    use crate::types::{
+        unix_timestamp, Account, DataAggregator, NativeTransfer, Retrieval, RetrievalError,
+        Transaction,
+    };
+
+    const TX_SIGNATURE: &str =
+        "5XiFRQDYp31KxFQtJqqrjTduTZnGaEWffmv4941D34VsX2GpYavU69bpn1xwWtrcS7fE7D5KuXCjpqjQwLHHeifZ";
+
+    fn mock_account(account_pubkey: Pubkey, lamports: u64) -> Account {
+        Account {
+            account_pubkey,
+            lamports,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            fetched_at: unix_timestamp(),
+            context_slot: 1,
+        }
+    }
+
+    fn mock_transaction() -> Transaction {
+        Transaction {
+            signature: TX_SIGNATURE.to_string(),
+            timestamp: 1720605742,
+            description: "".to_string(),
+            fee: 5001,
+            fee_payer: "38tFiQmLwmzUHYiCrYKH4pumqWxpdaYvErUsJbmeSZus".to_string(),
+            slot: 276738369,
+            native_transfers: Some(vec![NativeTransfer {
+                amount: 2039280,
+                from_user_account: Some("71eXHafHQ5mDf4ZeA1FPKsKQFR32TMQsq3wukuwyTSDe".to_string()),
+                to_user_account: Some("38tFiQmLwmzUHYiCrYKH4pumqWxpdaYvErUsJbmeSZus".to_string()),
+            }]),
+            ..Transaction::default()
+        }
+    }
+
+    fn mock_aggregator() -> (Arc<MockSource>, DataAggregator) {
+        let source = Arc::new(MockSource::new());
+        let aggregator = DataAggregator::new(Retrieval::new(source.clone()));
+
+        (source, aggregator)
+    }
+
+    #[tokio::test]
+    async fn fetch_account_from_mock_source() {
+        let (source, aggregator) = mock_aggregator();
+        let account_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(account_pubkey, 1_000_000_000));
+
+        let retrieval = &aggregator.retrieval;
+        let account = retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.lamports, 1_000_000_000);
+
+        let balances = retrieval.get_account_balances().await.unwrap();
+        assert_eq!(balances.get(&account_pubkey.to_string()), Some(&1.0));
+    }
+
+    #[tokio::test]
+    async fn missing_account_is_not_found_response() {
+        let (_source, aggregator) = mock_aggregator();
+        let account_id = Pubkey::new_unique().to_string();
+
+        let error = aggregator
+            .retrieval
+            .fetch_account(account_id.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            RetrievalError::NotFound(format!("Account {} not found", account_id))
+        );
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(
+            body["error"]["message"],
+            format!("Account {} not found", account_id)
+        );
+    }
+
+    #[test]
+    fn error_status_and_code_mapping() {
+        let errors = [
+            (
+                RetrievalError::NotFound("".into()),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                RetrievalError::UpstreamUnavailable("".into()),
+                StatusCode::BAD_GATEWAY,
+                "upstream_unavailable",
+            ),
+            (
+                RetrievalError::RateLimited("".into()),
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+            ),
+            (
+                RetrievalError::BudgetExhausted("".into()),
+                StatusCode::TOO_MANY_REQUESTS,
+                "budget_exhausted",
+            ),
+            (
+                RetrievalError::Timeout("".into()),
+                StatusCode::GATEWAY_TIMEOUT,
+                "timeout",
+            ),
+            (
+                RetrievalError::InvalidInput("".into()),
+                StatusCode::BAD_REQUEST,
+                "invalid_input",
+            ),
+            (
+                RetrievalError::Decode("".into()),
+                StatusCode::BAD_GATEWAY,
+                "decode_error",
+            ),
+            (
+                RetrievalError::from(anyhow::Error::msg("disk full")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "storage_error",
+            ),
+        ];
+
+        for (error, status, code) in errors {
+            assert_eq!(error.status(), status);
+            assert_eq!(error.code(), code);
+        }
+    }
+
+    #[tokio::test]
+    async fn refetch_account_older_than_max_age() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+        let account_pubkey = Pubkey::new_unique();
+        let account_id = account_pubkey.to_string();
+        source.set_account(Account {
+            fetched_at: unix_timestamp() - 60,
+            ..mock_account(account_pubkey, 1)
+        });
+        retrieval.fetch_account(account_id.clone()).await.unwrap();
+
+        let cached = retrieval
+            .get_fresh_account(account_id.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(cached.unwrap().context_slot, 1);
+        let cached = retrieval
+            .get_fresh_account(account_id.clone(), Some(120))
+            .await
+            .unwrap();
+        assert!(cached.is_some());
+        let stale = retrieval
+            .get_fresh_account(account_id.clone(), Some(10))
+            .await
+            .unwrap();
+        assert!(stale.is_none());
+        let missing = retrieval
+            .get_fresh_account(Pubkey::new_unique().to_string(), None)
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[tokio::test]
+    async fn keep_account_read_at_a_newer_slot() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+        let account_pubkey = Pubkey::new_unique();
+        let account_id = account_pubkey.to_string();
+
+        // A notification at slot 10 arrives before a fetch that read slot 5
+        retrieval
+            .store_account(Account {
+                context_slot: 10,
+                ..mock_account(account_pubkey, 2)
             })
-            .collect();
+            .await
+            .unwrap();
+        source.set_account(Account {
+            context_slot: 5,
+            ..mock_account(account_pubkey, 1)
+        });
+        let account = retrieval.fetch_account(account_id.clone()).await.unwrap();
+        assert_eq!((account.context_slot, account.lamports), (10, 2));
+
+        let report = retrieval.update_accounts(1).await.unwrap();
+        assert_eq!(report.updated, 1);
+        let cached = retrieval.get_fresh_account(account_id.clone(), None).await;
+        assert_eq!(cached.unwrap().unwrap().lamports, 2);
+
+        // ... and an older notification does not replace a newer fetch
+        source.set_account(Account {
+            context_slot: 12,
+            ..mock_account(account_pubkey, 3)
+        });
+        retrieval.fetch_account(account_id.clone()).await.unwrap();
+        retrieval
+            .store_account(Account {
+                context_slot: 11,
+                ..mock_account(account_pubkey, 4)
+            })
+            .await
+            .unwrap();
+        let cached = retrieval.get_fresh_account(account_id, None).await;
+        assert_eq!(cached.unwrap().unwrap().lamports, 3);
+    }
+
+    #[tokio::test]
+    async fn serve_stale_account_while_upstream_is_down() {
+        let source = Arc::new(MockSource::new());
+        let retrieval = Retrieval::new(source.clone()).with_stale_fallback(true);
+        let account_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(account_pubkey, 1_000));
+        retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap();
+
+        source.set_account(mock_account(account_pubkey, 2_000));
+        source.inject_faults(vec![RetrievalError::UpstreamUnavailable("down".into())]);
+        let account = retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.lamports, 1_000);
+
+        // Only transient errors fall back to the cache
+        source.inject_faults(vec![RetrievalError::Decode("garbage".into())]);
+        let error = retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "decode_error");
+
+        // Nothing cached to fall back to
+        source.inject_faults(vec![RetrievalError::Timeout("slow".into())]);
+        let error = retrieval
+            .fetch_account(Pubkey::new_unique().to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "timeout");
+    }
+
+    #[tokio::test]
+    async fn fetch_missing_account_from_mock_source() {
+        let (_source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+
+        let account = retrieval
+            .fetch_account(Pubkey::new_unique().to_string())
+            .await;
+        assert!(account.is_err());
+        assert_eq!(retrieval.get_account_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn update_accounts_from_mock_source() {
+        let (source, aggregator) = mock_aggregator();
+        let account_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(account_pubkey, 1));
+
+        let retrieval = &aggregator.retrieval;
+        retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap();
 
-        let transaction = Transaction {
-            signature: tx_response.signature.clone(),
-            timestamp: tx_response.timestamp,
-            description: tx_response.description.clone(),
-            fee: tx_response.fee,
-            fee_payer: tx_response.fee_payer.clone(),
-            slot: tx_response.slot,
-            native_transfers: Some(native_transfers),
+        source.set_account(mock_account(account_pubkey, 2));
+        let report = retrieval.update_accounts(4).await.unwrap();
+        assert_eq!(report.updated, 1);
+        assert!(report.failed.is_empty());
+
+        let account = retrieval
+            .get_account(account_pubkey.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.lamports, 2);
+    }
+
+    #[tokio::test]
+    async fn update_accounts_in_batches() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+
+        let account_pubkeys: Vec<Pubkey> = (0..250).map(|_| Pubkey::new_unique()).collect();
+        for account_pubkey in &account_pubkeys {
+            source.set_account(mock_account(*account_pubkey, 1));
+            retrieval
+                .fetch_account(account_pubkey.to_string())
+                .await
+                .unwrap();
+        }
+
+        for account_pubkey in &account_pubkeys {
+            source.set_account(mock_account(*account_pubkey, 2));
+        }
+        // The account disappears upstream, it should be reported without aborting the refresh
+        source.remove_account(&account_pubkeys[0]);
+
+        let report = retrieval.update_accounts(2).await.unwrap();
+        assert_eq!(source.multiple_accounts_calls(), 3);
+        assert_eq!(report.updated, 249);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].account_id, account_pubkeys[0].to_string());
+
+        let balances = retrieval.get_account_balances().await.unwrap();
+        assert_eq!(
+            balances.get(&account_pubkeys[0].to_string()),
+            Some(&0.000000001)
+        );
+        assert_eq!(
+            balances.get(&account_pubkeys[1].to_string()),
+            Some(&0.000000002)
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_transaction_from_mock_source() {
+        let (source, aggregator) = mock_aggregator();
+        source.set_transaction(mock_transaction());
+
+        let retrieval = &aggregator.retrieval;
+        assert!(retrieval
+            .get_cached_transaction(TX_SIGNATURE.to_string())
+            .await
+            .unwrap()
+            .is_none());
+        let transaction = retrieval
+            .fetch_transaction(TX_SIGNATURE.to_string())
+            .await
+            .unwrap();
+        assert_eq!(transaction.fee, 5001);
+        assert!(retrieval
+            .transaction_exists(TX_SIGNATURE.to_string())
+            .await
+            .unwrap());
+        let cached = retrieval
+            .get_cached_transaction(TX_SIGNATURE.to_string())
+            .await
+            .unwrap();
+        assert_eq!(cached.unwrap().fee, 5001);
+
+        let missing = retrieval
+            .fetch_transaction(
+                "3QGpxQhhDU2ijTnQhBEjYj28judQg7Ymrn5jZxMTmNKXqDSj2jSwNTB7Tfau6tkSF5rA7nT57HPbVeAxF9zpv25b"
+                    .to_string(),
+            )
+            .await;
+        assert!(missing.is_err());
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn fetch_transactions_in_one_batch() {
+        let (source, aggregator) = mock_aggregator();
+        let cached = block_transaction(&Signature::new_unique().to_string(), 1);
+        let fetched = block_transaction(&Signature::new_unique().to_string(), 2);
+        let missing = Signature::new_unique().to_string();
+        source.set_transaction(cached.clone());
+        source.set_transaction(fetched.clone());
+
+        let retrieval = &aggregator.retrieval;
+        retrieval
+            .fetch_transaction(cached.signature.clone())
+            .await
+            .unwrap();
+
+        let tx_signatures = vec![
+            cached.signature.clone(),
+            fetched.signature.clone(),
+            missing.clone(),
+            "invalid".to_string(),
+            fetched.signature.clone(),
+        ];
+        let lookups = retrieval.fetch_transactions(&tx_signatures).await.unwrap();
+
+        // One call for the single lookup and one for the batch misses
+        assert_eq!(source.parse_transactions_calls(), 2);
+        assert_eq!(lookups.len(), 5);
+        assert_eq!(lookups[0].transaction.as_ref().unwrap().slot, 1);
+        assert_eq!(lookups[1].transaction.as_ref().unwrap().slot, 2);
+        assert_eq!(lookups[2].signature, missing);
+        assert_eq!(lookups[2].error.as_ref().unwrap().code, "not_found");
+        assert!(lookups[3].transaction.is_none());
+        assert!(lookups[3].error.is_some());
+        assert!(lookups[4].transaction.is_some());
+        assert!(retrieval
+            .transaction_exists(fetched.signature.clone())
+            .await
+            .unwrap());
+
+        let too_many = vec![cached.signature; MAX_TRANSACTIONS_PER_BATCH + 1];
+        assert!(retrieval.fetch_transactions(&too_many).await.is_err());
+    }
+
+    fn raw_transaction(tx_signature: &str) -> serde_json::Value {
+        json!({
+            "signature": tx_signature,
+            "timestamp": 1720605742,
+            "fee": 5000,
+            "feePayer": "38tFiQmLwmzUHYiCrYKH4pumqWxpdaYvErUsJbmeSZus",
+            "slot": 276738369,
+            "nativeTransfers": [{ "amount": 2039280 }]
+        })
+    }
+
+    #[tokio::test]
+    async fn reject_malformed_upstream_payloads() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+
+        let missing_fee_payer = {
+            let mut payload = raw_transaction(TX_SIGNATURE);
+            payload.as_object_mut().unwrap().remove("feePayer");
+            payload
         };
+        let mut negative_amount = raw_transaction(TX_SIGNATURE);
+        negative_amount["nativeTransfers"][0]["amount"] = json!(-1);
+        let mut oversized_amount = raw_transaction(TX_SIGNATURE);
+        oversized_amount["nativeTransfers"][0]["amount"] =
+            json!("340282366920938463463374607431768211456");
+        let mut oversized_fee = raw_transaction(TX_SIGNATURE);
+        oversized_fee["fee"] = json!(u64::MAX);
+        let payloads = [
+            json!(null),
+            json!("not a transaction"),
+            missing_fee_payer,
+            negative_amount,
+            oversized_amount,
+            oversized_fee,
+        ];
 
-        self.database
-            .transactions
-            .insert(tx_response.signature.clone(), transaction.clone());
+        for payload in payloads {
+            source.set_raw_transactions(vec![payload]);
+            let error = retrieval
+                .fetch_transaction(TX_SIGNATURE.to_string())
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), "decode_error");
 
-        Ok(transaction)
+            let lookups = retrieval
+                .fetch_transactions(&[TX_SIGNATURE.to_string()])
+                .await
+                .unwrap();
+            assert_eq!(lookups[0].error.as_ref().unwrap().code, "decode_error");
+        }
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn empty_upstream_payload_is_not_found() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+        source.set_raw_transactions(vec![]);
+
+        let error = retrieval
+            .fetch_transaction(TX_SIGNATURE.to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "not_found");
+
+        let lookups = retrieval
+            .fetch_transactions(&[TX_SIGNATURE.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(lookups[0].error.as_ref().unwrap().code, "not_found");
+    }
+
+    #[tokio::test]
+    async fn ignore_unrequested_transactions_in_oversized_payload() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+
+        // The requested transaction comes last, after many that were not asked for
+        let mut payloads: Vec<serde_json::Value> = (0..500)
+            .map(|_| raw_transaction(&Signature::new_unique().to_string()))
+            .collect();
+        payloads.push(raw_transaction(TX_SIGNATURE));
+        source.set_raw_transactions(payloads);
+
+        let transaction = retrieval
+            .fetch_transaction(TX_SIGNATURE.to_string())
+            .await
+            .unwrap();
+        assert_eq!(transaction.signature, TX_SIGNATURE);
+        assert_eq!(transaction.native_transfers.unwrap()[0].amount, 2039280);
+
+        let missing = Signature::new_unique().to_string();
+        let lookups = retrieval.fetch_transactions(&[missing]).await.unwrap();
+        assert_eq!(lookups[0].error.as_ref().unwrap().code, "not_found");
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_account_without_upstream_call() {
+        let (source, aggregator) = mock_aggregator();
+
+        let error = aggregator
+            .retrieval
+            .fetch_account("not a pubkey".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "invalid_input");
+        assert_eq!(source.account_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn report_mismatched_multiple_accounts_response() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+        let account_pubkeys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for account_pubkey in &account_pubkeys {
+            source.set_account(mock_account(*account_pubkey, 1));
+            retrieval
+                .fetch_account(account_pubkey.to_string())
+                .await
+                .unwrap();
+        }
+
+        // A response shorter than the request fails the whole batch instead of misaligning it
+        source.set_multiple_accounts_response(vec![]);
+        let report = retrieval.update_accounts(1).await.unwrap();
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.failed.len(), 3);
+
+        // An entry for a different account is not stored under the requested key
+        source.set_multiple_accounts_response(vec![
+            Some(mock_account(Pubkey::new_unique(), 2)),
+            None,
+            None,
+        ]);
+        let report = retrieval.update_accounts(1).await.unwrap();
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.failed.len(), 3);
+        assert!(retrieval
+            .get_account_balances()
+            .await
+            .unwrap()
+            .values()
+            .all(|balance| *balance == 0.000000001));
+    }
+
+    #[tokio::test]
+    async fn page_account_transactions() {
+        let (source, aggregator) = mock_aggregator();
+        let account_pubkey = Pubkey::new_unique();
+        let transactions: Vec<Transaction> = (0..5)
+            .map(|slot| block_transaction(&Signature::new_unique().to_string(), 5 - slot))
+            .collect();
+        for transaction in &transactions {
+            source.set_transaction(transaction.clone());
+        }
+        source.set_address_signatures(
+            account_pubkey,
+            transactions
+                .iter()
+                .map(|transaction| transaction.signature.clone())
+                .collect(),
+        );
+
+        let retrieval = &aggregator.retrieval;
+        let account_id = account_pubkey.to_string();
+        let first_page = retrieval
+            .get_account_transactions(&account_id, None, None, 2)
+            .await
+            .unwrap();
+        let slots: Vec<i32> = first_page.transactions.iter().map(|tx| tx.slot).collect();
+        assert_eq!(slots, vec![5, 4]);
+        assert_eq!(
+            first_page.next_before.as_ref(),
+            Some(&transactions[1].signature)
+        );
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 2);
+
+        let last_page = retrieval
+            .get_account_transactions(&account_id, first_page.next_before, None, 10)
+            .await
+            .unwrap();
+        let slots: Vec<i32> = last_page.transactions.iter().map(|tx| tx.slot).collect();
+        assert_eq!(slots, vec![3, 2, 1]);
+        assert!(last_page.next_before.is_none());
+
+        let bounded_page = retrieval
+            .get_account_transactions(
+                &account_id,
+                None,
+                Some(transactions[2].signature.clone()),
+                10,
+            )
+            .await
+            .unwrap();
+        assert_eq!(bounded_page.transactions.len(), 2);
+        assert!(retrieval
+            .get_account_transactions("invalid", None, None, 10)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn read_cache_while_fetch_in_flight() {
+        let (source, aggregator) = mock_aggregator();
+        let cached_pubkey = Pubkey::new_unique();
+        let fetched_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(cached_pubkey, 1));
+        source.set_account(mock_account(fetched_pubkey, 2));
+        aggregator
+            .retrieval
+            .fetch_account(cached_pubkey.to_string())
+            .await
+            .unwrap();
+
+        source.set_delay(Duration::from_secs(5));
+        let fetching = aggregator.clone();
+        let fetch = tokio::spawn(async move {
+            fetching
+                .retrieval
+                .fetch_account(fetched_pubkey.to_string())
+                .await
+        });
+        tokio::task::yield_now().await;
+
+        // The slow upstream request does not hold up readers of the cache
+        let account = tokio::time::timeout(
+            Duration::from_millis(100),
+            aggregator.retrieval.get_account(cached_pubkey.to_string()),
+        )
+        .await
+        .expect("Cache read was blocked by the upstream fetch.")
+        .unwrap();
+        assert_eq!(account.lamports, 1);
+        fetch.abort();
+    }
+
+    /// Counts cache reads served in a second while slow upstream fetches are in flight,
+    /// compared with one RwLock held across the upstream call like the previous design.
+    /// Run with `cargo test --release bench_cache_reads -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_cache_reads_during_slow_fetches() {
+        const READERS: usize = 4;
+        let duration = Duration::from_secs(1);
+
+        let (source, aggregator) = mock_aggregator();
+        let cached_pubkey = Pubkey::new_unique();
+        let fetched_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(cached_pubkey, 1));
+        source.set_account(mock_account(fetched_pubkey, 2));
+        aggregator
+            .retrieval
+            .fetch_account(cached_pubkey.to_string())
+            .await
+            .unwrap();
+        source.set_delay(Duration::from_millis(50));
+
+        // Stands in for the RwLock that used to wrap the whole Retrieval
+        let global_lock = Arc::new(RwLock::new(()));
+        let mut results = vec![];
+        for locked in [true, false] {
+            let deadline = Instant::now() + duration;
+            let reads = Arc::new(AtomicUsize::new(0));
+
+            let writer = {
+                let aggregator = aggregator.clone();
+                let global_lock = global_lock.clone();
+                tokio::spawn(async move {
+                    while Instant::now() < deadline {
+                        let _guard = match locked {
+                            true => Some(global_lock.write().await),
+                            false => None,
+                        };
+                        let _ = aggregator
+                            .retrieval
+                            .fetch_account(fetched_pubkey.to_string())
+                            .await;
+                    }
+                })
+            };
+
+            let readers: Vec<_> = (0..READERS)
+                .map(|_| {
+                    let aggregator = aggregator.clone();
+                    let global_lock = global_lock.clone();
+                    let reads = reads.clone();
+                    tokio::spawn(async move {
+                        while Instant::now() < deadline {
+                            let _guard = match locked {
+                                true => Some(global_lock.read().await),
+                                false => None,
+                            };
+                            aggregator
+                                .retrieval
+                                .get_account(cached_pubkey.to_string())
+                                .await
+                                .unwrap();
+                            reads.fetch_add(1, Ordering::Relaxed);
+                            tokio::task::yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+
+            for reader in readers {
+                reader.await.unwrap();
+            }
+            writer.await.unwrap();
+            results.push(reads.load(Ordering::Relaxed));
+        }
+
+        println!(
+            "Cache reads per second during slow fetches [Global RwLock: {} Lock-free: {}]",
+            results[0], results[1]
+        );
+        assert!(results[1] > results[0]);
+    }
+
+    #[tokio::test]
+    async fn coalesce_concurrent_fetches() {
+        let (source, aggregator) = mock_aggregator();
+        let account_pubkey = Pubkey::new_unique();
+        source.set_account(mock_account(account_pubkey, 1));
+        source.set_transaction(mock_transaction());
+        source.set_delay(Duration::from_millis(50));
+
+        let accounts = futures::future::join_all((0..10).map(|_| {
+            aggregator
+                .retrieval
+                .fetch_account(account_pubkey.to_string())
+        }))
+        .await;
+        assert!(accounts.iter().all(|account| account.is_ok()));
+        assert_eq!(source.account_calls(), 1);
+
+        let missing = futures::future::join_all((0..10).map(|_| {
+            aggregator
+                .retrieval
+                .fetch_account(Pubkey::new_unique().to_string())
+        }))
+        .await;
+        assert!(missing.iter().all(|account| account.is_err()));
+        assert_eq!(source.account_calls(), 11);
+
+        let transactions = futures::future::join_all((0..10).map(|_| {
+            aggregator
+                .retrieval
+                .fetch_transaction(TX_SIGNATURE.to_string())
+        }))
+        .await;
+        assert!(transactions.iter().all(|transaction| transaction.is_ok()));
+        assert_eq!(source.parse_transactions_calls(), 1);
     }
 
-    pub async fn get_transaction(&self, tx_signature: String) -> Result<Transaction, Error> {
-        match self.database.transactions.get(&tx_signature) {
-            Some(transaction) => Ok(transaction.clone()),
-            None => Err(Error::msg("Transaction not found")),
+    fn block_transaction(signature: &str, slot: i32) -> Transaction {
+        Transaction {
+            signature: signature.to_string(),
+            slot,
+            native_transfers: None,
+            ..mock_transaction()
         }
     }
 
-    pub async fn transaction_exists(&self, tx_hash: String) -> Result<bool, Error> {
-        Ok(self.database.transactions.contains_key(&tx_hash))
+    #[tokio::test]
+    async fn crawl_blocks_and_resume_from_checkpoint() {
+        let path =
+            std::env::temp_dir().join(format!("data_aggregator_{}.sqlite", Pubkey::new_unique()));
+        let source = Arc::new(MockSource::new());
+        source.set_slot(14);
+        source.set_block(
+            10,
+            vec![block_transaction("a", 10), block_transaction("b", 10)],
+        );
+        source.set_block(11, vec![block_transaction("c", 11)]);
+        // Slot 12 is skipped
+        source.set_block(13, vec![]);
+        source.set_block(14, vec![block_transaction("d", 14)]);
+
+        {
+            let database = Box::new(SqliteDatabase::open(&path).unwrap());
+            let retrieval = Retrieval::with_database(source.clone(), database);
+
+            let report = retrieval.update_transactions(Some(10), 3).await.unwrap();
+            assert_eq!(report.processed_slots, 3);
+            assert_eq!(report.skipped_slots, 1);
+            assert_eq!(report.transactions, 3);
+            assert_eq!(report.last_slot, Some(12));
+        }
+
+        // After a restart the crawl resumes from the checkpoint and ignores start_slot
+        let database = Box::new(SqliteDatabase::open(&path).unwrap());
+        let retrieval = Retrieval::with_database(source.clone(), database);
+
+        let report = retrieval.update_transactions(Some(10), 3).await.unwrap();
+        assert_eq!(report.processed_slots, 2);
+        assert_eq!(report.transactions, 1);
+        assert_eq!(report.last_slot, Some(14));
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 4);
+        assert_eq!(
+            retrieval
+                .get_transaction("d".to_string())
+                .await
+                .unwrap()
+                .slot,
+            14
+        );
+
+        // Nothing to crawl until a new slot is produced
+        let report = retrieval.update_transactions(Some(10), 3).await.unwrap();
+        assert_eq!(report.processed_slots, 0);
+        assert_eq!(report.last_slot, None);
+
+        std::fs::remove_file(path).unwrap();
     }
 
-    // TODO: Implement update_transactions based on some criteria.
-    // This can also be achieved by crawling block by block
-    // from a certain block height, similar to normal block indexers.
-    // Multiprocess communication can also be useful here.
-    // pub async fn update_transactions(&mut self) -> Result<(), Error> {
-    //     Ok(())
-    // }
+    #[tokio::test]
+    async fn skip_block_failing_repeatedly() {
+        let (source, aggregator) = mock_aggregator();
+        let retrieval = &aggregator.retrieval;
+        source.set_slot(12);
+        source.set_block(10, vec![block_transaction("a", 10)]);
+        source.set_block_error(11, RetrievalError::Decode("unknown version".into()));
+        source.set_block(12, vec![block_transaction("b", 12)]);
+
+        // The slot before the failing block is checkpointed, the crawl stops at the failing one
+        let error = retrieval
+            .update_transactions(Some(10), 5)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "decode_error");
+        assert!(retrieval.update_transactions(Some(10), 5).await.is_err());
+
+        // An unavailable upstream does not count as an attempt
+        source.inject_faults(vec![RetrievalError::UpstreamUnavailable("down".into())]);
+        let error = retrieval
+            .update_transactions(Some(10), 5)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "upstream_unavailable");
+
+        let report = retrieval.update_transactions(Some(10), 5).await.unwrap();
+        assert_eq!(report.failed_slots, vec![11]);
+        assert_eq!(report.processed_slots, 2);
+        assert_eq!(report.last_slot, Some(12));
+        assert_eq!(retrieval.get_transaction_count().await.unwrap(), 2);
+    }
 }
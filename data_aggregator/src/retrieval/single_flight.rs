@@ -0,0 +1,138 @@
+use std::{collections::HashMap, future::Future, sync::Mutex};
+use tokio::sync::watch;
+
+use crate::types::RetrievalError;
+
+// Outcome shared with the waiting callers
+type SharedResult<T> = Option<Result<T, RetrievalError>>;
+
+/// Deduplicates concurrent upstream requests for the same key.
+/// The first caller (the leader) runs the request, callers arriving while it is in flight
+/// wait for its result or error instead of sending their own request.
+pub struct SingleFlight<T> {
+    in_flight: Mutex<HashMap<String, watch::Receiver<SharedResult<T>>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+// Removes the key when the leader finishes or its future is dropped, e.g. on a request timeout
+struct InFlightGuard<'a, T> {
+    single_flight: &'a SingleFlight<T>,
+    key: &'a str,
+}
+
+impl<T> Drop for InFlightGuard<'_, T> {
+    fn drop(&mut self) {
+        self.single_flight
+            .in_flight
+            .lock()
+            .unwrap()
+            .remove(self.key);
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub async fn run<F, Fut>(&self, key: &str, request: F) -> Result<T, RetrievalError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, RetrievalError>>,
+    {
+        let sender = loop {
+            let mut receiver = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get(key) {
+                    Some(receiver) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        in_flight.insert(key.to_string(), receiver);
+                        break sender;
+                    }
+                }
+            };
+
+            // A dropped sender means the leader was cancelled, the next caller takes over
+            let shared = match receiver.wait_for(Option::is_some).await {
+                Ok(shared) => shared.clone(),
+                Err(_) => None,
+            };
+            if let Some(result) = shared {
+                return result;
+            }
+        };
+
+        let _guard = InFlightGuard {
+            single_flight: self,
+            key,
+        };
+        let result = request().await;
+        sender.send_replace(Some(result.clone()));
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod single_flight_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn share_result_and_error() {
+        let single_flight = SingleFlight::<u64>::default();
+        let requests = AtomicUsize::new(0);
+        let request = |result: Result<u64, RetrievalError>| {
+            let requests = &requests;
+            move || async move {
+                requests.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                result
+            }
+        };
+
+        let results =
+            futures::future::join_all((0..10).map(|_| single_flight.run("key", request(Ok(7)))))
+                .await;
+        assert!(results.iter().all(|result| *result.as_ref().unwrap() == 7));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let results = futures::future::join_all((0..10).map(|_| {
+            single_flight.run(
+                "key",
+                request(Err(RetrievalError::UpstreamUnavailable(
+                    "upstream down".to_string(),
+                ))),
+            )
+        }))
+        .await;
+        assert!(results
+            .iter()
+            .all(|result| result.as_ref().unwrap_err().code() == "upstream_unavailable"));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn take_over_after_cancelled_leader() {
+        let single_flight = SingleFlight::<u64>::default();
+
+        let leader = single_flight.run("key", || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(1)
+        });
+        let follower = single_flight.run("key", || async { Ok(2) });
+
+        // The leader is dropped before it completes and the waiting follower runs its request
+        let (cancelled, result) = tokio::join!(
+            tokio::time::timeout(Duration::from_millis(10), leader),
+            follower
+        );
+        assert!(cancelled.is_err());
+        assert_eq!(result.unwrap(), 2);
+    }
+}
@@ -1,30 +1,106 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use axum::{extract::Path, routing::get, Extension, Json, Router};
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+    routing::{get, post},
+    Extension, Json, Router,
+};
 use futures::future::join_all;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
-use tokio::task::{self};
-use tokio::time::interval;
-use tower_http::timeout::TimeoutLayer;
+use tokio::task::{self, AbortHandle, JoinHandle};
+use tokio::time::{interval, Instant};
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    timeout::TimeoutLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{debug, info, warn, Level, Span};
 
-use crate::types::{Account, AppError, DataAggregator, Retrieval, Transaction};
+use crate::cluster::{Clusters, PathCluster, SelectedCluster};
+use crate::config::{Config, CrawlerConfig, MonitorMode, ServerConfig};
+use crate::health::{CacheSizes, Health, Readiness};
+use crate::logging::request_span;
+use crate::metrics::{track_http_requests, METRICS};
+use crate::retrieval::{DEFAULT_HISTORY_LIMIT, MAX_TRANSACTIONS_PER_BATCH};
+use crate::source::{prioritize_user_requests, ProvidersStatus};
+use crate::storage::CacheStats;
+use crate::subscription::{run_subscriptions, SubscriptionStatus};
+use crate::supervisor::{Supervisor, TaskStatus};
+use crate::types::{
+    unix_timestamp, Account, AccountQuery, DataAggregator, RetrievalError, Transaction,
+    TransactionHistoryQuery, TransactionLookup, TransactionPage, TransactionsRequest,
+};
 
-async fn server_log(aggregator: DataAggregator, interval_in_sec: u64) -> Result<(), anyhow::Error> {
+/// Cancels the shutdown token on SIGINT (Ctrl+C) or SIGTERM
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let interrupt = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            warn!(%error, "Cannot listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => _ = signal.recv().await,
+            Err(error) => {
+                warn!(%error, "Cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("SIGINT received, shutting down"),
+        _ = terminate => info!("SIGTERM received, shutting down"),
+        _ = shutdown.cancelled() => return,
+    }
+    shutdown.cancel();
+}
+
+async fn server_log(
+    aggregator: DataAggregator,
+    interval_in_sec: u64,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let mut interval = interval(Duration::from_secs(interval_in_sec));
 
     loop {
         tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
                 _ = interval.tick() => {
-                    let retrieval = aggregator.retrieval.read().await;
+                    let retrieval = &aggregator.retrieval;
+
+                    // Balances of every cached account are only listed at the debug level
+                    if tracing::enabled!(Level::DEBUG) {
+                        match retrieval.get_account_balances().await {
+                            Ok(balances) => debug!(?balances, "Cached account balances"),
+                            Err(error) => warn!(%error, "Cached account balances are not available"),
+                        }
+                    }
 
-                    let balances = retrieval.get_account_balances().await;
-                    println!("DB accounts with balances: {:?}", balances);
+                    match (retrieval.get_account_count().await, retrieval.get_transaction_count().await) {
+                        (Ok(accounts), Ok(transactions)) => info!(accounts, transactions, "Cache status"),
+                        (Err(error), _) | (_, Err(error)) => warn!(%error, "Cache status is not available"),
+                    }
 
-                    let accounts = retrieval.get_account_count().await;
-                    let transactions = retrieval.get_transaction_count().await;
-                    println!("DB cache status [Transactions: {:?} Accounts: {:?}]", transactions, accounts);
+                    if let Ok(Some(stats)) = retrieval.get_cache_stats().await {
+                        info!(
+                            hits = stats.hits,
+                            misses = stats.misses,
+                            evictions = stats.evictions,
+                            bytes = stats.bytes,
+                            "Cache usage"
+                        );
+                    }
             }
         }
     }
@@ -33,159 +109,526 @@ async fn server_log(aggregator: DataAggregator, interval_in_sec: u64) -> Result<
 async fn server_monitor(
     aggregator: DataAggregator,
     interval_in_sec: u64,
+    max_concurrent_batches: usize,
+    subscription_status: Option<Arc<SubscriptionStatus>>,
+    health: Arc<Health>,
+    shutdown: CancellationToken,
 ) -> Result<(), anyhow::Error> {
     let mut interval = interval(Duration::from_secs(interval_in_sec));
 
     loop {
         tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = interval.tick() => {
+                    // In push mode accounts are polled only while the WebSocket is unavailable
+                    if let Some(status) = subscription_status.as_ref().filter(|status| status.is_connected()) {
+                        // A connected socket that stopped sending slot updates leaves the monitor stalled
+                        if status.slot_advanced() {
+                            debug!(slot = status.slot(), "Accounts updated by subscriptions");
+                            health.record_monitor_refresh();
+                        } else {
+                            warn!(slot = status.slot(), "No slot update received from the subscriptions");
+                        }
+                        continue;
+                    }
+
+                    let started = Instant::now();
+                    let report = aggregator
+                        .retrieval
+                        .update_accounts(max_concurrent_batches)
+                        .await?;
+                    METRICS.monitor_cycle_duration.observe(started.elapsed().as_secs_f64());
+                    health.record_monitor_refresh();
+
+                    info!(
+                        updated = report.updated,
+                        failed = report.failed.len(),
+                        duration_ms = started.elapsed().as_millis() as u64,
+                        "Accounts updated"
+                    );
+                    for failure in report.failed {
+                        warn!(account = %failure.account_id, error = %failure.error, "Account update failed");
+                    }
+            }
+        }
+    }
+}
+
+async fn server_crawler(
+    aggregator: DataAggregator,
+    config: CrawlerConfig,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let mut interval = interval(Duration::from_secs(config.interval_secs));
+
+    loop {
+        tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
                 _ = interval.tick() => {
-                    aggregator.retrieval.write().await.update_accounts().await?;
+                    let report = aggregator
+                        .retrieval
+                        .update_transactions(config.start_slot, config.max_slots_per_cycle)
+                        .await?;
 
-                    println!("Accounts updated");
+                    if let Some(last_slot) = report.last_slot {
+                        info!(
+                            slots = report.processed_slots,
+                            skipped = report.skipped_slots,
+                            failed = report.failed_slots.len(),
+                            transactions = report.transactions,
+                            last_slot,
+                            "Blocks crawled"
+                        );
+                    }
             }
         }
     }
 }
 
 async fn get_account(
-    Extension(aggregator): Extension<DataAggregator>,
+    SelectedCluster(cluster): SelectedCluster,
     Path(account_id): Path<String>,
-) -> Result<Json<Account>, AppError> {
+    Query(query): Query<AccountQuery>,
+) -> Result<Json<Account>, RetrievalError> {
+    Span::current().record("account", account_id.as_str());
+
     // account_id validation
     account_id
         .as_str()
         .parse::<Pubkey>()
-        .map_err(|_| AppError::BadRequest("Account validation failed.".into()))?;
+        .map_err(|_| RetrievalError::InvalidInput("Account validation failed.".into()))?;
 
+    // Cache reads and upstream fetches run without a global lock
+    let aggregator = &cluster.aggregator;
+    match aggregator
+        .retrieval
+        .get_fresh_account(account_id.clone(), query.max_age)
+        .await?
     {
-        // Check if the account exists in the cache
-        let read_lock = aggregator.retrieval.read().await;
-        match read_lock.account_exists(account_id.clone()).await {
-            Ok(true) => {
-                // If it exists, retrieve it from the cache
-                return read_lock
-                    .get_account(account_id)
-                    .await
-                    .map(Json)
-                    .map_err(|_| {
-                        AppError::InternalServerError("Failed to get account from cache.".into())
-                    });
-            }
-            Ok(false) => {
-                // Proceed to fetch the account from the external source later
-            }
-            Err(_) => {
-                return Err(AppError::InternalServerError(
-                    "Account existence check failed.".into(),
-                ))
-            }
-        }
+        // If it is cached and fresh enough, serve it from the cache
+        Some(account) => Ok(Json(account)),
+        // Fetch the account from the external source and store it
+        None => aggregator
+            .retrieval
+            .fetch_account(account_id)
+            .await
+            .map(Json),
     }
+}
 
-    // Acquire a write lock to fetch and store the account
-    let mut write_lock = aggregator.retrieval.write().await;
-    write_lock
-        .fetch_account(account_id)
+async fn get_account_transactions(
+    SelectedCluster(cluster): SelectedCluster,
+    Path(account_id): Path<String>,
+    Query(query): Query<TransactionHistoryQuery>,
+) -> Result<Json<TransactionPage>, RetrievalError> {
+    Span::current().record("account", account_id.as_str());
+
+    // account_id and cursors validation
+    account_id
+        .as_str()
+        .parse::<Pubkey>()
+        .map_err(|_| RetrievalError::InvalidInput("Account validation failed.".into()))?;
+    for cursor in [&query.before, &query.until].into_iter().flatten() {
+        Signature::from_str(cursor).map_err(|_| {
+            RetrievalError::InvalidInput("Invalid transaction signature format.".into())
+        })?;
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if limit == 0 || limit > MAX_TRANSACTIONS_PER_BATCH {
+        return Err(RetrievalError::InvalidInput(format!(
+            "Limit must be between 1 and {}.",
+            MAX_TRANSACTIONS_PER_BATCH
+        )));
+    }
+
+    cluster
+        .aggregator
+        .retrieval
+        .get_account_transactions(&account_id, query.before, query.until, limit)
         .await
         .map(Json)
-        .map_err(|_| AppError::InternalServerError("Failed to fetch account.".into()))
 }
 
 async fn get_transaction(
-    Extension(aggregator): Extension<DataAggregator>,
+    SelectedCluster(cluster): SelectedCluster,
     Path(tx_signature): axum::extract::Path<String>,
-) -> Result<Json<Transaction>, AppError> {
+) -> Result<Json<Transaction>, RetrievalError> {
+    Span::current().record("signature", tx_signature.as_str());
+
     // tx_signature validation
-    Signature::from_str(&tx_signature)
-        .map_err(|_| AppError::BadRequest("Invalid transaction signature format.".into()))?;
+    Signature::from_str(&tx_signature).map_err(|_| {
+        RetrievalError::InvalidInput("Invalid transaction signature format.".into())
+    })?;
 
+    let aggregator = &cluster.aggregator;
+    match aggregator
+        .retrieval
+        .get_cached_transaction(tx_signature.clone())
+        .await?
     {
-        // Check if the transaction exists in the cache
-        let read_lock = aggregator.retrieval.read().await;
-        match read_lock.transaction_exists(tx_signature.clone()).await {
-            Ok(true) => {
-                // If it exists, retrieve it from the cache
-                return read_lock
-                    .get_transaction(tx_signature)
-                    .await
-                    .map(Json)
-                    .map_err(|_| {
-                        AppError::InternalServerError("Failed to get transaction from cache.".into())
-                    });
-            }
-            Ok(false) => {
-                // Proceed to fetch the transaction from the external source later
-            }
-            Err(_) => {
-                return Err(AppError::InternalServerError(
-                    "Transaction existence check failed.".into(),
-                ))
-            }
-        }
+        // If it is cached, serve it from the cache
+        Some(transaction) => Ok(Json(transaction)),
+        // Fetch the transaction from the external source and store it
+        None => aggregator
+            .retrieval
+            .fetch_transaction(tx_signature)
+            .await
+            .map(Json),
+    }
+}
+
+async fn get_transactions(
+    SelectedCluster(cluster): SelectedCluster,
+    Json(request): Json<TransactionsRequest>,
+) -> Result<Json<Vec<TransactionLookup>>, RetrievalError> {
+    // Signatures are validated one by one, only the batch size is rejected as a whole
+    if request.signatures.is_empty() || request.signatures.len() > MAX_TRANSACTIONS_PER_BATCH {
+        return Err(RetrievalError::InvalidInput(format!(
+            "Between 1 and {} signatures are expected.",
+            MAX_TRANSACTIONS_PER_BATCH
+        )));
     }
 
-    // Acquire a write lock to fetch and store the transaction
-    let mut write_lock = aggregator.retrieval.write().await;
-    write_lock
-        .fetch_transaction(tx_signature)
+    cluster
+        .aggregator
+        .retrieval
+        .fetch_transactions(&request.signatures)
         .await
         .map(Json)
-        .map_err(|_| AppError::InternalServerError("Failed to fetch transaction.".into()))
+}
+
+async fn get_cache_stats(
+    SelectedCluster(cluster): SelectedCluster,
+) -> Result<Json<CacheStats>, RetrievalError> {
+    cluster
+        .aggregator
+        .retrieval
+        .get_cache_stats()
+        .await?
+        .map(Json)
+        .ok_or_else(|| RetrievalError::NotFound("Cache statistics are not available.".into()))
+}
+
+async fn get_metrics(
+    Extension(clusters): Extension<Arc<Clusters>>,
+) -> Result<String, RetrievalError> {
+    // Cache sizes are read on scrape instead of being tracked on every write
+    for cluster in clusters.iter() {
+        let retrieval = &cluster.aggregator.retrieval;
+        let name = cluster.cluster.as_str();
+        METRICS
+            .cached_entries
+            .with_label_values(&[name, "account"])
+            .set(retrieval.get_account_count().await? as i64);
+        METRICS
+            .cached_entries
+            .with_label_values(&[name, "transaction"])
+            .set(retrieval.get_transaction_count().await? as i64);
+    }
+
+    Ok(METRICS.render())
+}
+
+async fn get_live() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "live" }))
+}
+
+async fn get_ready(
+    SelectedCluster(cluster): SelectedCluster,
+) -> Result<(StatusCode, Json<Readiness>), RetrievalError> {
+    let retrieval = &cluster.aggregator.retrieval;
+    let cache = CacheSizes {
+        accounts: retrieval.get_account_count().await?,
+        transactions: retrieval.get_transaction_count().await?,
+    };
+    let readiness = cluster.health.readiness(unix_timestamp(), cache);
+
+    // The orchestrator only looks at the status, the body explains it
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((status, Json(readiness)))
+}
+
+async fn get_tasks(
+    Extension(supervisor): Extension<Arc<Supervisor>>,
+) -> Json<BTreeMap<String, TaskStatus>> {
+    Json(supervisor.statuses())
+}
+
+async fn get_providers(SelectedCluster(cluster): SelectedCluster) -> Json<ProvidersStatus> {
+    Json(cluster.providers.status())
 }
 
 async fn run_axum_serve(
-    aggregator: DataAggregator,
-    close_rx: tokio::sync::oneshot::Receiver<()>,
+    clusters: Arc<Clusters>,
+    supervisor: Arc<Supervisor>,
+    config: ServerConfig,
+    shutdown: CancellationToken,
 ) -> Result<(), anyhow::Error> {
-    let app = Router::new()
-        .route("/", get(|| async { "Ping? Pong!" }))
+    // Routes served per cluster, by the default cluster or the one of the cluster query
+    // parameter at the root, by the cluster of the prefix under e.g. /devnet
+    let cluster_routes = Router::new()
         .route("/account/:account_id", get(get_account))
+        .route(
+            "/account/:account_id/transactions",
+            get(get_account_transactions),
+        )
         .route("/transaction/:tx_signature", get(get_transaction))
-        .layer(TimeoutLayer::new(Duration::from_secs(10)))
-        .layer(Extension(aggregator));
+        .route("/transactions", post(get_transactions))
+        .route("/cache/stats", get(get_cache_stats))
+        .route("/health/ready", get(get_ready))
+        .route("/admin/providers", get(get_providers));
 
-    let address = "127.0.0.1:3000";
-    let listener = tokio::net::TcpListener::bind(address)
-        .await
-        .expect("Tcp listener failed.");
+    let mut app = Router::new()
+        .route("/", get(|| async { "Ping? Pong!" }))
+        .route("/metrics", get(get_metrics))
+        .route("/health/live", get(get_live))
+        .route("/tasks", get(get_tasks))
+        .merge(cluster_routes.clone());
+    for cluster in clusters.iter() {
+        app = app.nest(
+            &format!("/{}", cluster.cluster.as_str()),
+            cluster_routes
+                .clone()
+                .layer(Extension(PathCluster(cluster.cluster))),
+        );
+    }
+
+    let app = app
+        .route_layer(middleware::from_fn(track_http_requests))
+        .route_layer(middleware::from_fn(prioritize_user_requests))
+        .route_layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            config.request_timeout_secs,
+        )))
+        .layer(Extension(clusters))
+        .layer(Extension(supervisor));
+
+    let address = config.bind_address.as_str();
+    let listener = tokio::net::TcpListener::bind(address).await?;
 
-    println!("Starting server at {:?}", address);
+    info!(address, "Starting server");
 
+    // Stops accepting connections on shutdown and waits for in-flight requests
     axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            _ = close_rx.await;
-        })
-        .await
-        .expect("Server start failed.");
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
 
     Ok(())
 }
 
-pub async fn run_server() -> Result<(), anyhow::Error> {
-    let aggregator = DataAggregator::new(Retrieval::new());
+pub async fn run_server(config: Config) -> Result<(), anyhow::Error> {
+    // Every cluster has its own providers, cache, health and background tasks
+    let clusters = Arc::new(Clusters::new(&config)?);
+
+    // Cancelled on SIGINT or SIGTERM, stops the HTTP server and all background tasks
+    let shutdown = CancellationToken::new();
+    task::spawn(cancel_on_signal(shutdown.clone()));
 
-    // Aggregator background tasks
+    // Aggregator background tasks, restarted with backoff when they fail
+    let supervisor = Arc::new(Supervisor::default());
     let mut tasks = vec![];
 
-    let log_handle = task::spawn(server_log(aggregator.clone(), 3));
-    tasks.push(log_handle);
+    for cluster in clusters.iter() {
+        let aggregator = cluster.aggregator.clone();
+
+        let (log_aggregator, log_interval_secs, log_shutdown) = (
+            aggregator.clone(),
+            config.server.log_interval_secs,
+            shutdown.clone(),
+        );
+        tasks.push(
+            supervisor.spawn(cluster.task_name("log"), shutdown.clone(), move || {
+                server_log(
+                    log_aggregator.clone(),
+                    log_interval_secs,
+                    log_shutdown.clone(),
+                )
+            }),
+        );
+
+        let subscription_status = match config.monitor.mode {
+            MonitorMode::Push => {
+                let status = Arc::new(SubscriptionStatus::default());
+                // monitor.ws_url only overrides the WebSocket of the default cluster
+                let ws_url = config
+                    .monitor
+                    .ws_url
+                    .clone()
+                    .filter(|_| cluster.default)
+                    .unwrap_or_else(|| config.helius_for(cluster.cluster).ws_url());
+
+                let (subscriptions_aggregator, subscriptions_status, resubscribe_interval_secs) = (
+                    aggregator.clone(),
+                    status.clone(),
+                    config.monitor.interval_secs,
+                );
+                let subscriptions_shutdown = shutdown.clone();
+                tasks.push(supervisor.spawn(
+                    cluster.task_name("subscriptions"),
+                    shutdown.clone(),
+                    move || {
+                        run_subscriptions(
+                            subscriptions_aggregator.clone(),
+                            ws_url.clone(),
+                            subscriptions_status.clone(),
+                            resubscribe_interval_secs,
+                            subscriptions_shutdown.clone(),
+                        )
+                    },
+                ));
+
+                Some(status)
+            }
+            MonitorMode::Poll => None,
+        };
+
+        let (monitor_aggregator, monitor_health, monitor_shutdown) =
+            (aggregator.clone(), cluster.health.clone(), shutdown.clone());
+        let (monitor_interval_secs, max_concurrent_batches) = (
+            config.monitor.interval_secs,
+            config.monitor.max_concurrent_batches,
+        );
+        tasks.push(
+            supervisor.spawn(cluster.task_name("monitor"), shutdown.clone(), move || {
+                server_monitor(
+                    monitor_aggregator.clone(),
+                    monitor_interval_secs,
+                    max_concurrent_batches,
+                    subscription_status.clone(),
+                    monitor_health.clone(),
+                    monitor_shutdown.clone(),
+                )
+            }),
+        );
+    }
 
-    let monitor_handle = task::spawn(server_monitor(aggregator.clone(), 6));
-    tasks.push(monitor_handle);
+    // Blocks are only crawled on the default cluster, the start slot is specific to it
+    if config.crawler.enabled {
+        let (crawler_aggregator, crawler_config, crawler_shutdown) = (
+            clusters.default_cluster().aggregator.clone(),
+            config.crawler.clone(),
+            shutdown.clone(),
+        );
+        tasks.push(supervisor.spawn("crawler", shutdown.clone(), move || {
+            server_crawler(
+                crawler_aggregator.clone(),
+                crawler_config.clone(),
+                crawler_shutdown.clone(),
+            )
+        }));
+    }
+
+    let shutdown_timeout_secs = config.server.shutdown_timeout_secs;
+    let serve = run_axum_serve(
+        clusters.clone(),
+        supervisor,
+        config.server,
+        shutdown.clone(),
+    );
+    tokio::pin!(serve);
 
-    let (_close_tx, close_rx) = tokio::sync::oneshot::channel();
+    // The server only stops by itself when it fails, e.g. the address is already taken
+    let served = tokio::select! {
+        result = &mut serve => Some(result),
+        _ = shutdown.cancelled() => None,
+    };
+    shutdown.cancel();
 
-    // TODO: This could be handled with a tasks vector and join_all(tasks), but there is a type problem.
-    // I'm sure it's fixable since I did something similar a couple of weeks ago.
-    run_axum_serve(aggregator.clone(), close_rx).await?;
+    // In-flight requests and the current cycle of every background task are drained until the deadline
+    let abort_handles: Vec<AbortHandle> = tasks.iter().map(JoinHandle::abort_handle).collect();
+    let drained = tokio::time::timeout(Duration::from_secs(shutdown_timeout_secs), async {
+        let served = match served {
+            Some(result) => result,
+            None => serve.await,
+        };
+        (served, join_all(tasks).await)
+    })
+    .await;
 
-    // Join all aggregator background tasks
-    let results = join_all(tasks).await;
+    let (served, results) = match drained {
+        Ok(drained) => drained,
+        Err(_) => {
+            warn!(
+                shutdown_timeout_secs,
+                "Shutdown deadline exceeded, in-flight work is aborted"
+            );
+            abort_handles.iter().for_each(AbortHandle::abort);
+            (Ok(()), vec![])
+        }
+    };
 
-    // Handle the results of the tasks
+    // Every write is already stored, this only flushes what the backend buffers
+    for cluster in clusters.iter() {
+        cluster.aggregator.retrieval.flush().await?;
+    }
+    info!("Shutdown complete");
+
+    served?;
     for result in results {
         result??;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod server_tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{server_crawler, server_log, server_monitor};
+    use crate::config::{CrawlerConfig, HealthConfig};
+    use crate::health::Health;
+    use crate::source::mock::MockSource;
+    use crate::types::{DataAggregator, Retrieval};
+
+    #[tokio::test]
+    async fn stop_background_tasks_on_shutdown() {
+        let aggregator = DataAggregator::new(Retrieval::new(Arc::new(MockSource::new())));
+        let health = Arc::new(Health::new(&HealthConfig::default(), 60));
+        let shutdown = CancellationToken::new();
+
+        let tasks = vec![
+            tokio::spawn(server_log(aggregator.clone(), 60, shutdown.clone())),
+            tokio::spawn(server_monitor(
+                aggregator.clone(),
+                60,
+                1,
+                None,
+                health,
+                shutdown.clone(),
+            )),
+            tokio::spawn(server_crawler(
+                aggregator.clone(),
+                CrawlerConfig {
+                    interval_secs: 60,
+                    ..CrawlerConfig::default()
+                },
+                shutdown.clone(),
+            )),
+        ];
+
+        // Let every task finish its first cycle, then they wait for the next tick
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+
+        let results =
+            tokio::time::timeout(Duration::from_secs(1), futures::future::join_all(tasks))
+                .await
+                .expect("Background tasks did not stop on shutdown.");
+        for result in results {
+            result.unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::types::{Account, RetrievalError, Transaction};
+
+mod enhanced;
+pub(crate) mod failover;
+mod helius;
+mod instrumented;
+mod limited;
+#[cfg(test)]
+pub mod mock;
+mod resilient;
+mod rpc;
+
+pub use self::failover::{FailoverSource, ProvidersStatus};
+pub use self::helius::HeliusSource;
+pub use self::instrumented::InstrumentedSource;
+pub use self::limited::{prioritize_user_requests, RateLimitedSource, RateLimiter};
+pub use self::resilient::ResilientSource;
+pub use self::rpc::RpcSource;
+
+/// ChainSource is the upstream provider of chain data used by Retrieval.
+/// Implementations map provider specific responses and errors into the aggregator types.
+#[async_trait]
+pub trait ChainSource: Send + Sync {
+    /// Fetches the current state of a single account
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError>;
+
+    /// Fetches the current state of many accounts in one upstream request.
+    /// The result has the same order as the keys, with None for accounts that do not exist.
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError>;
+
+    /// Fetches and parses transactions by their signatures.
+    /// Signatures unknown to the provider are omitted from the result.
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError>;
+
+    /// Signatures of transactions that touched an account, newest first.
+    /// Paging starts after `before` and stops at `until`, both exclusive.
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError>;
+
+    /// Latest finalized slot
+    async fn get_slot(&self) -> Result<u64, RetrievalError>;
+
+    /// Fetches all transactions of a block, or None when the slot was skipped
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError>;
+}
@@ -0,0 +1,492 @@
+use anyhow::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::types::{
+    AccountBalanceChange, InnerInstruction, Instruction, NativeAmount, NativeTransfer, NftEvent,
+    RetrievalError, SwapEvent, TokenBalanceChange, TokenTransfer, Transaction, TransactionEvents,
+};
+
+// The mapping works on the JSON wire format of the Helius enhanced transactions API,
+// so it does not depend on the type layout of the helius crate and the same parser
+// can be fed raw upstream payloads.
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusTransaction {
+    signature: String,
+    timestamp: u64,
+    #[serde(default)]
+    description: String,
+    #[serde(rename = "type", default)]
+    transaction_type: Option<String>,
+    #[serde(default)]
+    source: Option<String>,
+    fee: i64,
+    fee_payer: String,
+    slot: i64,
+    #[serde(default)]
+    transaction_error: Option<Value>,
+    #[serde(default)]
+    native_transfers: Option<Vec<HeliusNativeTransfer>>,
+    #[serde(default)]
+    token_transfers: Option<Vec<HeliusTokenTransfer>>,
+    #[serde(default)]
+    account_data: Option<Vec<HeliusAccountData>>,
+    #[serde(default)]
+    instructions: Option<Vec<HeliusInstruction>>,
+    #[serde(default)]
+    events: Option<HeliusEvents>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusNativeTransfer {
+    from_user_account: Option<String>,
+    to_user_account: Option<String>,
+    amount: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusTokenTransfer {
+    from_user_account: Option<String>,
+    to_user_account: Option<String>,
+    from_token_account: Option<String>,
+    to_token_account: Option<String>,
+    token_amount: f64,
+    #[serde(default)]
+    token_standard: Option<String>,
+    mint: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusAccountData {
+    account: String,
+    // Payloads re-serialized from the helius crate types carry nativeTokenBalance instead
+    #[serde(default)]
+    native_balance_change: i64,
+    #[serde(default)]
+    token_balance_changes: Option<Vec<HeliusTokenBalanceChange>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusTokenBalanceChange {
+    user_account: String,
+    token_account: String,
+    mint: String,
+    raw_token_amount: HeliusRawTokenAmount,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusRawTokenAmount {
+    token_amount: String,
+    decimals: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusInstruction {
+    program_id: String,
+    #[serde(default)]
+    accounts: Vec<String>,
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    inner_instructions: Option<Vec<HeliusInnerInstruction>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeliusInnerInstruction {
+    program_id: String,
+    #[serde(default)]
+    accounts: Vec<String>,
+    #[serde(default)]
+    data: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HeliusEvents {
+    swap: Option<HeliusSwapEvent>,
+    nft: Option<HeliusNftEvent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct HeliusSwapEvent {
+    native_input: Option<HeliusNativeAmount>,
+    native_output: Option<HeliusNativeAmount>,
+    token_inputs: Option<Vec<HeliusTokenBalanceChange>>,
+    token_outputs: Option<Vec<HeliusTokenBalanceChange>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeliusNativeAmount {
+    account: String,
+    amount: Value,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct HeliusNftEvent {
+    #[serde(rename = "type")]
+    event_type: Option<String>,
+    source: Option<String>,
+    description: Option<String>,
+    amount: Option<Value>,
+    fee: Option<Value>,
+    sale_type: Option<String>,
+    buyer: Option<String>,
+    seller: Option<String>,
+    nfts: Option<Vec<HeliusNft>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeliusNft {
+    mint: String,
+}
+
+/// Lamport amounts are sent either as numbers or as numeric strings
+fn parse_amount(amount: &Value) -> Result<u64, Error> {
+    match amount {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => text.parse::<u64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| Error::msg(format!("Amount cannot be parsed: {}", amount)))
+}
+
+fn map_token_balance_change(change: HeliusTokenBalanceChange) -> TokenBalanceChange {
+    TokenBalanceChange {
+        user_account: change.user_account,
+        token_account: change.token_account,
+        mint: change.mint,
+        raw_token_amount: change.raw_token_amount.token_amount,
+        decimals: change.raw_token_amount.decimals,
+    }
+}
+
+fn map_native_amount(
+    native_amount: Option<HeliusNativeAmount>,
+) -> Result<Option<NativeAmount>, Error> {
+    native_amount
+        .map(|native_amount| {
+            Ok(NativeAmount {
+                amount: parse_amount(&native_amount.amount)?,
+                account: native_amount.account,
+            })
+        })
+        .transpose()
+}
+
+fn map_events(events: HeliusEvents) -> Result<TransactionEvents, Error> {
+    let swap = match events.swap {
+        Some(swap) => Some(SwapEvent {
+            native_input: map_native_amount(swap.native_input)?,
+            native_output: map_native_amount(swap.native_output)?,
+            token_inputs: swap
+                .token_inputs
+                .unwrap_or_default()
+                .into_iter()
+                .map(map_token_balance_change)
+                .collect(),
+            token_outputs: swap
+                .token_outputs
+                .unwrap_or_default()
+                .into_iter()
+                .map(map_token_balance_change)
+                .collect(),
+        }),
+        None => None,
+    };
+
+    let nft = match events.nft {
+        Some(nft) => Some(NftEvent {
+            event_type: nft.event_type.unwrap_or_default(),
+            source: nft.source.unwrap_or_default(),
+            description: nft.description.unwrap_or_default(),
+            amount: nft
+                .amount
+                .as_ref()
+                .map(parse_amount)
+                .transpose()?
+                .unwrap_or_default(),
+            fee: nft
+                .fee
+                .as_ref()
+                .map(parse_amount)
+                .transpose()?
+                .unwrap_or_default(),
+            sale_type: nft.sale_type.unwrap_or_default(),
+            buyer: nft.buyer.unwrap_or_default(),
+            seller: nft.seller.unwrap_or_default(),
+            mints: nft
+                .nfts
+                .unwrap_or_default()
+                .into_iter()
+                .map(|nft| nft.mint)
+                .collect(),
+        }),
+        None => None,
+    };
+
+    Ok(TransactionEvents { swap, nft })
+}
+
+/// Maps a single transaction returned by the Helius enhanced transactions API
+fn parse_enhanced_transaction(value: Value) -> Result<Transaction, Error> {
+    let tx: HeliusTransaction = serde_json::from_value(value)?;
+
+    let native_transfers = tx
+        .native_transfers
+        .map(|native_transfers| {
+            native_transfers
+                .into_iter()
+                .map(|native_transfer| {
+                    Ok(NativeTransfer {
+                        amount: parse_amount(&native_transfer.amount)?,
+                        from_user_account: native_transfer.from_user_account,
+                        to_user_account: native_transfer.to_user_account,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        })
+        .transpose()?;
+
+    let account_data: Vec<AccountBalanceChange> = tx
+        .account_data
+        .unwrap_or_default()
+        .into_iter()
+        .map(|account_data| AccountBalanceChange {
+            account: account_data.account,
+            native_balance_change: account_data.native_balance_change,
+            token_balance_changes: account_data
+                .token_balance_changes
+                .unwrap_or_default()
+                .into_iter()
+                .map(map_token_balance_change)
+                .collect(),
+        })
+        .collect();
+
+    // Token transfers carry only the UI amount, decimals come from the balance changes
+    let decimals: HashMap<&str, u8> = account_data
+        .iter()
+        .flat_map(|account_data| &account_data.token_balance_changes)
+        .map(|change| (change.mint.as_str(), change.decimals))
+        .collect();
+
+    let token_transfers = tx.token_transfers.map(|token_transfers| {
+        token_transfers
+            .into_iter()
+            .map(|token_transfer| TokenTransfer {
+                decimals: decimals.get(token_transfer.mint.as_str()).copied(),
+                mint: token_transfer.mint,
+                token_amount: token_transfer.token_amount,
+                token_standard: token_transfer.token_standard.unwrap_or_default(),
+                from_user_account: token_transfer.from_user_account,
+                to_user_account: token_transfer.to_user_account,
+                from_token_account: token_transfer.from_token_account,
+                to_token_account: token_transfer.to_token_account,
+            })
+            .collect()
+    });
+
+    let instructions = tx
+        .instructions
+        .unwrap_or_default()
+        .into_iter()
+        .map(|instruction| Instruction {
+            program_id: instruction.program_id,
+            accounts: instruction.accounts,
+            data: instruction.data,
+            inner_instructions: instruction
+                .inner_instructions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|inner_instruction| InnerInstruction {
+                    program_id: inner_instruction.program_id,
+                    accounts: inner_instruction.accounts,
+                    data: inner_instruction.data,
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Transaction {
+        signature: tx.signature,
+        timestamp: tx.timestamp,
+        description: tx.description,
+        transaction_type: tx.transaction_type.unwrap_or_default(),
+        source: tx.source.unwrap_or_default(),
+        fee: i32::try_from(tx.fee)?,
+        fee_payer: tx.fee_payer,
+        slot: i32::try_from(tx.slot)?,
+        transaction_error: tx.transaction_error.map(|error| error.to_string()),
+        native_transfers,
+        token_transfers,
+        account_data,
+        instructions,
+        events: map_events(tx.events.unwrap_or_default())?,
+    })
+}
+
+/// Maps a single transaction, a malformed payload is reported as a decode error
+pub fn decode_enhanced_transaction(value: Value) -> Result<Transaction, RetrievalError> {
+    parse_enhanced_transaction(value)
+        .map_err(|error| RetrievalError::Decode(format!("{:#}", error)))
+}
+
+#[cfg(test)]
+mod enhanced_tests {
+    use super::*;
+    use serde_json::json;
+
+    fn swap_payload() -> Value {
+        json!({
+            "signature": "5wHu1qwD7q5ifaN5nwdcDqNFo53GJqa7nLp2BeeEpcHCusb4GzARz4GjgzsEHMkBMgCJMGa6GSQ1VG96Exv8kt2W",
+            "timestamp": 1704067200,
+            "description": "trader swapped 1 SOL for 100 USDC",
+            "type": "SWAP",
+            "source": "JUPITER",
+            "fee": 5000,
+            "feePayer": "trader",
+            "slot": 240000000,
+            "transactionError": null,
+            "nativeTransfers": [
+                { "fromUserAccount": "trader", "toUserAccount": "pool", "amount": 1000000000 }
+            ],
+            "tokenTransfers": [{
+                "fromUserAccount": "pool",
+                "toUserAccount": "trader",
+                "fromTokenAccount": "pool-usdc",
+                "toTokenAccount": "trader-usdc",
+                "tokenAmount": 100.0,
+                "tokenStandard": "Fungible",
+                "mint": "usdc"
+            }],
+            "accountData": [{
+                "account": "trader",
+                "nativeBalanceChange": -1000005000,
+                "tokenBalanceChanges": [{
+                    "userAccount": "trader",
+                    "tokenAccount": "trader-usdc",
+                    "mint": "usdc",
+                    "rawTokenAmount": { "tokenAmount": "100000000", "decimals": 6 }
+                }]
+            }],
+            "instructions": [{
+                "programId": "jupiter",
+                "accounts": ["trader", "pool"],
+                "data": "3Bxs4",
+                "innerInstructions": [
+                    { "programId": "token", "accounts": ["pool-usdc", "trader-usdc"], "data": "3Dc8" }
+                ]
+            }],
+            "events": {
+                "swap": {
+                    "nativeInput": { "account": "trader", "amount": "1000000000" },
+                    "nativeOutput": null,
+                    "tokenInputs": [],
+                    "tokenOutputs": [{
+                        "userAccount": "trader",
+                        "tokenAccount": "trader-usdc",
+                        "mint": "usdc",
+                        "rawTokenAmount": { "tokenAmount": "100000000", "decimals": 6 }
+                    }]
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn parse_swap_transaction() {
+        let transaction = parse_enhanced_transaction(swap_payload()).unwrap();
+
+        assert_eq!(transaction.transaction_type, "SWAP");
+        assert_eq!(transaction.source, "JUPITER");
+        assert_eq!(transaction.fee, 5000);
+        assert_eq!(transaction.transaction_error, None);
+        assert_eq!(
+            transaction.native_transfers.unwrap()[0].amount,
+            1_000_000_000
+        );
+
+        let token_transfer = &transaction.token_transfers.unwrap()[0];
+        assert_eq!(token_transfer.mint, "usdc");
+        assert_eq!(token_transfer.decimals, Some(6));
+        assert_eq!(
+            token_transfer.to_token_account.as_deref(),
+            Some("trader-usdc")
+        );
+
+        assert_eq!(
+            transaction.account_data[0].native_balance_change,
+            -1_000_005_000
+        );
+        assert_eq!(
+            transaction.account_data[0].token_balance_changes[0].raw_token_amount,
+            "100000000"
+        );
+        assert_eq!(transaction.instructions[0].inner_instructions.len(), 1);
+
+        let swap = transaction.events.swap.unwrap();
+        assert_eq!(swap.native_input.unwrap().amount, 1_000_000_000);
+        assert!(swap.native_output.is_none());
+        assert_eq!(swap.token_outputs[0].decimals, 6);
+        assert!(transaction.events.nft.is_none());
+    }
+
+    #[test]
+    fn parse_minimal_transaction() {
+        let transaction = parse_enhanced_transaction(json!({
+            "signature": "signature",
+            "timestamp": 1704067200,
+            "fee": 5000,
+            "feePayer": "payer",
+            "slot": 240000000,
+            "transactionError": { "InstructionError": [0, "InvalidAccountData"] }
+        }))
+        .unwrap();
+
+        assert_eq!(transaction.transaction_type, "");
+        assert!(transaction.native_transfers.is_none());
+        assert!(transaction.account_data.is_empty());
+        assert!(transaction
+            .transaction_error
+            .unwrap()
+            .contains("InvalidAccountData"));
+    }
+
+    #[test]
+    fn parse_serialized_helius_transaction() {
+        // The helius swap event requires fields that the fixture leaves out
+        let mut payload = swap_payload();
+        payload["events"] = json!({});
+        let helius_transaction: helius::types::EnhancedTransaction =
+            serde_json::from_value(payload).unwrap();
+        let payload = serde_json::to_value(&helius_transaction).unwrap();
+
+        let transaction = decode_enhanced_transaction(payload).unwrap();
+        assert_eq!(transaction.fee, 5000);
+        assert_eq!(transaction.account_data[0].account, "trader");
+        assert_eq!(transaction.account_data[0].native_balance_change, 0);
+        assert_eq!(
+            transaction.account_data[0].token_balance_changes[0].raw_token_amount,
+            "100000000"
+        );
+    }
+
+    #[test]
+    fn reject_invalid_amount() {
+        let mut payload = swap_payload();
+        payload["nativeTransfers"][0]["amount"] = json!("not a number");
+        assert!(parse_enhanced_transaction(payload).is_err());
+    }
+}
@@ -0,0 +1,492 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::config::{HeliusConfig, ProviderConfig, ProviderKind};
+use crate::metrics::METRICS;
+use crate::source::{ChainSource, HeliusSource, RateLimitedSource, RateLimiter, RpcSource};
+use crate::types::{Account, RetrievalError, Transaction};
+
+// Weight of the latest call in the latency and error rate moving averages
+const SMOOTHING: f64 = 0.2;
+// The error rate halves every half-life, so a provider that failed is preferred again over time
+const ERROR_HALF_LIFE: Duration = Duration::from_secs(30);
+
+struct ProviderHealth {
+    // Moving averages of answered calls latency and of transient failures (0 to 1)
+    latency_ms: f64,
+    error_rate: f64,
+    // When the error rate was last updated, it decays from there
+    updated_at: Instant,
+    requests: u64,
+    failures: u64,
+    last_error: Option<String>,
+}
+
+impl ProviderHealth {
+    fn error_rate(&self, now: Instant) -> f64 {
+        let half_lives = now.saturating_duration_since(self.updated_at).as_secs_f64()
+            / ERROR_HALF_LIFE.as_secs_f64();
+        self.error_rate * 0.5f64.powf(half_lives)
+    }
+}
+
+/// An upstream provider with its weight and health
+pub struct Provider {
+    name: String,
+    kind: ProviderKind,
+    weight: u32,
+    source: Arc<dyn ChainSource>,
+    health: Mutex<ProviderHealth>,
+}
+
+impl Provider {
+    pub fn new(
+        name: String,
+        kind: ProviderKind,
+        weight: u32,
+        source: Arc<dyn ChainSource>,
+    ) -> Self {
+        Provider {
+            name,
+            kind,
+            weight,
+            source,
+            health: Mutex::new(ProviderHealth {
+                latency_ms: 0.0,
+                error_rate: 0.0,
+                updated_at: Instant::now(),
+                requests: 0,
+                failures: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    fn supports(&self, method: &str) -> bool {
+        // Only Helius provides enhanced transactions
+        self.kind == ProviderKind::Helius || method != "parse_transactions"
+    }
+
+    /// Transient errors count as failures, any other result is an answer of the provider
+    fn record<T>(&self, result: &Result<T, RetrievalError>, latency: Duration) {
+        // The call was stopped by the credit budget or not supported, it never reached the provider
+        if matches!(
+            result,
+            Err(RetrievalError::BudgetExhausted(_) | RetrievalError::Unsupported(_))
+        ) {
+            return;
+        }
+        let mut health = self.health.lock().unwrap();
+        let now = Instant::now();
+        let failed = matches!(result, Err(error) if error.is_transient());
+
+        health.error_rate =
+            health.error_rate(now) * (1.0 - SMOOTHING) + if failed { SMOOTHING } else { 0.0 };
+        health.updated_at = now;
+        health.requests += 1;
+        match result {
+            Err(error) if failed => {
+                health.failures += 1;
+                health.last_error = Some(error.to_string());
+            }
+            _ => {
+                let latency_ms = latency.as_secs_f64() * 1000.0;
+                health.latency_ms = if health.requests == 1 {
+                    latency_ms
+                } else {
+                    health.latency_ms * (1.0 - SMOOTHING) + latency_ms * SMOOTHING
+                };
+            }
+        }
+    }
+
+    /// The weight, scaled down by the error rate and by every second of latency
+    fn score(&self, now: Instant) -> f64 {
+        let health = self.health.lock().unwrap();
+        let latency_secs = health.latency_ms / 1000.0;
+        f64::from(self.weight) * (1.0 - health.error_rate(now)) / (1.0 + latency_secs)
+    }
+
+    fn status(&self, now: Instant) -> ProviderStatus {
+        let score = self.score(now);
+        let health = self.health.lock().unwrap();
+        ProviderStatus {
+            name: self.name.clone(),
+            kind: self.kind,
+            weight: self.weight,
+            score,
+            latency_ms: health.latency_ms,
+            error_rate: health.error_rate(now),
+            requests: health.requests,
+            failures: health.failures,
+            last_error: health.last_error.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderStatus {
+    pub name: String,
+    pub kind: ProviderKind,
+    pub weight: u32,
+    pub score: f64,
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub requests: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+/// Providers from the best to the worst score, the first one serves requests
+#[derive(Debug, Serialize)]
+pub struct ProvidersStatus {
+    pub active: Option<String>,
+    pub providers: Vec<ProviderStatus>,
+}
+
+/// ChainSource spreading calls over several providers. Every call goes to the provider with
+/// the best score that supports the method, and fails over to the next one on transient errors.
+pub struct FailoverSource {
+    providers: Vec<Provider>,
+}
+
+impl FailoverSource {
+    pub fn new(providers: Vec<Provider>) -> Self {
+        FailoverSource { providers }
+    }
+
+    /// Helius providers are rate limited and charged credits by the limiter,
+    /// JSON-RPC nodes are neither
+    pub fn from_config(
+        providers: &[ProviderConfig],
+        helius: &HeliusConfig,
+        limiter: &Arc<RateLimiter>,
+    ) -> Result<Self, RetrievalError> {
+        let providers = providers
+            .iter()
+            .map(|provider| {
+                let source: Arc<dyn ChainSource> = match (provider.kind, &provider.url) {
+                    (ProviderKind::Helius, _) => Arc::new(RateLimitedSource::new(
+                        Arc::new(HeliusSource::new(helius)?),
+                        limiter.clone(),
+                    )),
+                    (ProviderKind::Rpc, Some(url)) => Arc::new(RpcSource::new(url.clone())),
+                    (ProviderKind::Rpc, None) => {
+                        return Err(RetrievalError::InvalidInput(format!(
+                            "Provider {} has no url",
+                            provider.name
+                        )))
+                    }
+                };
+                Ok(Provider::new(
+                    provider.name.clone(),
+                    provider.kind,
+                    provider.weight,
+                    source,
+                ))
+            })
+            .collect::<Result<_, RetrievalError>>()?;
+
+        Ok(Self::new(providers))
+    }
+
+    // Ties keep the configured order
+    fn ranked(&self, method: &str) -> Vec<&Provider> {
+        let now = Instant::now();
+        let mut providers: Vec<(f64, &Provider)> = self
+            .providers
+            .iter()
+            .filter(|provider| provider.supports(method))
+            .map(|provider| (provider.score(now), provider))
+            .collect();
+        providers.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        providers
+            .into_iter()
+            .map(|(_, provider)| provider)
+            .collect()
+    }
+
+    pub fn status(&self) -> ProvidersStatus {
+        let now = Instant::now();
+        // Every provider serves the plain JSON-RPC methods
+        let providers: Vec<ProviderStatus> = self
+            .ranked("get_account")
+            .into_iter()
+            .map(|provider| provider.status(now))
+            .collect();
+
+        ProvidersStatus {
+            active: providers.first().map(|provider| provider.name.clone()),
+            providers,
+        }
+    }
+
+    async fn call<'s, T, F, Fut>(&'s self, method: &str, call: F) -> Result<T, RetrievalError>
+    where
+        F: Fn(&'s dyn ChainSource) -> Fut,
+        Fut: Future<Output = Result<T, RetrievalError>>,
+    {
+        let mut last_error =
+            RetrievalError::Unsupported(format!("No configured provider supports {}", method));
+
+        for provider in self.ranked(method) {
+            let started = Instant::now();
+            let result = call(provider.source.as_ref()).await;
+            provider.record(&result, started.elapsed());
+
+            match result {
+                Err(error) if error.is_transient() => {
+                    warn!(provider = provider.name, method, %error, "Upstream provider failed");
+                    METRICS
+                        .upstream_failovers
+                        .with_label_values(&[&provider.name])
+                        .inc();
+                    last_error = error;
+                }
+                // Only the calls of this provider are charged, another one can serve the call
+                Err(error @ RetrievalError::BudgetExhausted(_)) => {
+                    debug!(
+                        provider = provider.name,
+                        method, "Upstream provider out of credits"
+                    );
+                    last_error = error;
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+#[async_trait]
+impl ChainSource for FailoverSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.call("get_account", |source| source.get_account(account_pubkey))
+            .await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.call("get_multiple_accounts", |source| {
+            source.get_multiple_accounts(account_pubkeys)
+        })
+        .await
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        self.call("parse_transactions", |source| {
+            source.parse_transactions(tx_signatures.clone())
+        })
+        .await
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.call("get_signatures_for_address", |source| {
+            source.get_signatures_for_address(account_pubkey, before.clone(), until.clone(), limit)
+        })
+        .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.call("get_slot", |source| source.get_slot()).await
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.call("get_block", |source| source.get_block(slot))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod failover_tests {
+    use super::*;
+    use crate::source::mock::MockSource;
+    use axum::http::StatusCode;
+
+    fn failover(weights: [u32; 2]) -> (Arc<MockSource>, Arc<MockSource>, FailoverSource) {
+        let (helius, rpc) = (Arc::new(MockSource::new()), Arc::new(MockSource::new()));
+        let source = FailoverSource::new(vec![
+            Provider::new(
+                "helius".to_string(),
+                ProviderKind::Helius,
+                weights[0],
+                helius.clone(),
+            ),
+            Provider::new(
+                "rpc".to_string(),
+                ProviderKind::Rpc,
+                weights[1],
+                rpc.clone(),
+            ),
+        ]);
+
+        (helius, rpc, source)
+    }
+
+    #[tokio::test]
+    async fn fail_over_on_transient_errors() {
+        let (helius, rpc, source) = failover([2, 1]);
+        assert_eq!(source.status().active.as_deref(), Some("helius"));
+
+        helius.inject_faults(vec![RetrievalError::Timeout("slow".into())]);
+        rpc.set_slot(7);
+        assert_eq!(source.get_slot().await.unwrap(), 7);
+
+        // The upstream answered, the other provider would not know better
+        helius.inject_faults(vec![RetrievalError::NotFound("unknown".into())]);
+        let error = source.get_slot().await.unwrap_err();
+        assert_eq!(error.code(), "not_found");
+
+        helius.inject_faults(vec![RetrievalError::UpstreamUnavailable("down".into())]);
+        rpc.inject_faults(vec![RetrievalError::RateLimited("429".into())]);
+        let error = source.get_slot().await.unwrap_err();
+        assert_eq!(error.code(), "rate_limited");
+
+        let status = source.status();
+        let helius_status = status
+            .providers
+            .iter()
+            .find(|provider| provider.name == "helius")
+            .unwrap();
+        assert_eq!(helius_status.requests, 3);
+        assert_eq!(helius_status.failures, 2);
+        assert!(helius_status.last_error.as_ref().unwrap().contains("down"));
+    }
+
+    #[tokio::test]
+    async fn prefer_healthy_provider_over_weight() {
+        let (helius, _, source) = failover([2, 1]);
+
+        // Four failures in a row bring the error rate of helius to about 0.59
+        for _ in 0..4 {
+            helius.inject_faults(vec![RetrievalError::Timeout("slow".into())]);
+            source.get_slot().await.unwrap();
+        }
+        let status = source.status();
+        assert_eq!(status.active.as_deref(), Some("rpc"));
+        assert!(status.providers[1].error_rate > 0.55);
+
+        // Calls now start with rpc, helius is not asked
+        source.get_slot().await.unwrap();
+        let status = source.status();
+        assert_eq!(status.providers[0].requests, 5);
+        assert_eq!(status.providers[1].requests, 4);
+
+        // The error rate decays, helius is preferred again after a few half-lives
+        let later = Instant::now() + ERROR_HALF_LIFE * 4;
+        assert!(source.providers[0].score(later) > source.providers[1].score(later));
+    }
+
+    #[tokio::test]
+    async fn fail_over_when_credits_are_spent() {
+        use crate::config::RateLimitConfig;
+
+        let (helius, rpc) = (Arc::new(MockSource::new()), Arc::new(MockSource::new()));
+        let limiter = Arc::new(RateLimiter::new(&RateLimitConfig {
+            requests_per_sec: 1000.0,
+            daily_credits: Some(2),
+            user_reserve_percent: 0,
+            ..RateLimitConfig::default()
+        }));
+        let source = FailoverSource::new(vec![
+            Provider::new(
+                "helius".to_string(),
+                ProviderKind::Helius,
+                2,
+                Arc::new(RateLimitedSource::new(helius.clone(), limiter)),
+            ),
+            Provider::new("rpc".to_string(), ProviderKind::Rpc, 1, rpc.clone()),
+        ]);
+
+        for _ in 0..3 {
+            let error = source.get_account(&Pubkey::new_unique()).await.unwrap_err();
+            assert_eq!(error.code(), "not_found");
+        }
+        assert_eq!(helius.account_calls(), 2);
+        assert_eq!(rpc.account_calls(), 1);
+
+        // Spent credits do not make the provider unhealthy
+        let status = source.status();
+        assert_eq!(status.active.as_deref(), Some("helius"));
+        assert_eq!(status.providers[0].requests, 2);
+        assert_eq!(status.providers[0].failures, 0);
+    }
+
+    #[tokio::test]
+    async fn refuse_enhanced_transactions_without_helius() {
+        use crate::config::{HealthConfig, UpstreamConfig};
+        use crate::health::{CacheSizes, Health};
+        use crate::source::{InstrumentedSource, ResilientSource};
+        use crate::types::unix_timestamp;
+
+        let rpc = Arc::new(MockSource::new());
+        let providers = Arc::new(FailoverSource::new(vec![Provider::new(
+            "rpc".to_string(),
+            ProviderKind::Rpc,
+            1,
+            rpc.clone(),
+        )]));
+        let health = Arc::new(Health::new(&HealthConfig::default(), 6));
+        let config = UpstreamConfig {
+            breaker_failure_threshold: 1,
+            ..UpstreamConfig::default()
+        };
+        let source = ResilientSource::new(
+            Arc::new(InstrumentedSource::new(providers.clone(), health.clone())),
+            &config,
+            "rpc-only",
+        );
+
+        for _ in 0..3 {
+            let error = source
+                .parse_transactions(vec!["sig".into()])
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), "unsupported");
+            assert_eq!(error.status(), StatusCode::NOT_IMPLEMENTED);
+        }
+        assert_eq!(rpc.parse_transactions_calls(), 0);
+
+        // Neither the breaker, the readiness nor the provider health count the refused calls
+        rpc.set_slot(7);
+        assert_eq!(source.get_slot().await.unwrap(), 7);
+        let cache = CacheSizes {
+            accounts: 0,
+            transactions: 0,
+        };
+        let readiness = health.readiness(unix_timestamp(), cache);
+        assert!(readiness.upstream.reachable);
+        assert_eq!(readiness.upstream.consecutive_failures, 0);
+        let status = providers.status();
+        assert_eq!(status.providers[0].requests, 1);
+        assert_eq!(status.providers[0].failures, 0);
+    }
+
+    #[tokio::test]
+    async fn skip_rpc_providers_for_enhanced_transactions() {
+        let (helius, rpc, source) = failover([1, 5]);
+        assert_eq!(source.status().active.as_deref(), Some("rpc"));
+
+        source.parse_transactions(vec!["sig".into()]).await.unwrap();
+        assert_eq!(helius.parse_transactions_calls(), 1);
+        assert_eq!(rpc.parse_transactions_calls(), 0);
+    }
+}
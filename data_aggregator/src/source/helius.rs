@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use helius::{error::HeliusError, types::ParseTransactionsRequest, Helius};
+use reqwest::{Method, Url};
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::config::HeliusConfig;
+use crate::source::{enhanced::decode_enhanced_transaction, ChainSource, RpcSource};
+use crate::types::{Account, RetrievalError, Transaction};
+
+impl From<HeliusError> for RetrievalError {
+    fn from(error: HeliusError) -> Self {
+        let message = error.to_string();
+        match error {
+            HeliusError::RateLimitExceeded { .. } => RetrievalError::RateLimited(message),
+            HeliusError::Timeout { .. } => RetrievalError::Timeout(message),
+            HeliusError::NotFound { .. } => RetrievalError::NotFound(message),
+            HeliusError::BadRequest { .. } | HeliusError::InvalidInput(_) => {
+                RetrievalError::InvalidInput(message)
+            }
+            HeliusError::SerdeJson(_) => RetrievalError::Decode(message),
+            _ => RetrievalError::UpstreamUnavailable(message),
+        }
+    }
+}
+
+/// ChainSource backed by the Helius RPC and enhanced transactions API
+pub struct HeliusSource {
+    helius: Helius,
+    // Plain JSON-RPC methods go to the Helius RPC endpoint
+    rpc: RpcSource,
+}
+
+impl HeliusSource {
+    pub fn new(config: &HeliusConfig) -> Result<Self, RetrievalError> {
+        let helius = Helius::new(&config.api_key, config.cluster.into())?;
+
+        Ok(HeliusSource {
+            helius,
+            rpc: RpcSource::new(config.rpc_url()),
+        })
+    }
+}
+
+#[async_trait]
+impl ChainSource for HeliusSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.rpc.get_account(account_pubkey).await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.rpc.get_multiple_accounts(account_pubkeys).await
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        let request: ParseTransactionsRequest = ParseTransactionsRequest {
+            transactions: tx_signatures,
+        };
+        // The raw payload is decoded instead of the helius crate types, which drop fields such as
+        // nativeBalanceChange and cannot be serialized back to the wire format
+        let url = format!(
+            "{}v0/transactions?api-key={}",
+            self.helius.config.endpoints.api, self.helius.config.api_key
+        );
+        let url =
+            Url::parse(&url).map_err(|error| RetrievalError::InvalidInput(error.to_string()))?;
+        let tx_responses: Vec<Value> = self
+            .helius
+            .rpc_client
+            .handler
+            .send(Method::POST, url, Some(&request))
+            .await?;
+
+        let transactions = tx_responses
+            .into_iter()
+            .map(decode_enhanced_transaction)
+            .collect::<Result<Vec<_>, RetrievalError>>()?;
+
+        Ok(transactions)
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.rpc
+            .get_signatures_for_address(account_pubkey, before, until, limit)
+            .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.rpc.get_slot().await
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.rpc.get_block(slot).await
+    }
+}
@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, Span};
+
+use crate::health::Health;
+use crate::metrics::METRICS;
+use crate::source::ChainSource;
+use crate::types::{Account, RetrievalError, Transaction};
+
+/// ChainSource decorator recording the count, latency and errors of upstream calls.
+/// Outcomes also feed the upstream health reported by the readiness endpoint.
+pub struct InstrumentedSource {
+    inner: Arc<dyn ChainSource>,
+    health: Arc<Health>,
+}
+
+impl InstrumentedSource {
+    pub fn new(inner: Arc<dyn ChainSource>, health: Arc<Health>) -> Self {
+        InstrumentedSource { inner, health }
+    }
+
+    async fn observe<T>(
+        &self,
+        method: &str,
+        call: impl Future<Output = Result<T, RetrievalError>>,
+    ) -> Result<T, RetrievalError> {
+        let started = Instant::now();
+        let result = call.await;
+        let elapsed = started.elapsed();
+
+        // Upstream latency of the request that triggered the call, see logging::request_span
+        Span::current().record("upstream_latency_ms", elapsed.as_millis() as u64);
+        debug!(
+            method,
+            latency_ms = elapsed.as_millis() as u64,
+            "Upstream call"
+        );
+
+        METRICS
+            .upstream_request_duration
+            .with_label_values(&[method])
+            .observe(elapsed.as_secs_f64());
+        METRICS.upstream_requests.with_label_values(&[method]).inc();
+        if let Err(error) = &result {
+            METRICS
+                .upstream_errors
+                .with_label_values(&[method, error.code()])
+                .inc();
+        }
+        self.health.record_upstream(&result);
+
+        result
+    }
+}
+
+#[async_trait]
+impl ChainSource for InstrumentedSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.observe("get_account", self.inner.get_account(account_pubkey))
+            .await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.observe(
+            "get_multiple_accounts",
+            self.inner.get_multiple_accounts(account_pubkeys),
+        )
+        .await
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        self.observe(
+            "parse_transactions",
+            self.inner.parse_transactions(tx_signatures),
+        )
+        .await
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.observe(
+            "get_signatures_for_address",
+            self.inner
+                .get_signatures_for_address(account_pubkey, before, until, limit),
+        )
+        .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.observe("get_slot", self.inner.get_slot()).await
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.observe("get_block", self.inner.get_block(slot)).await
+    }
+}
+
+#[cfg(test)]
+mod instrumented_tests {
+    use super::*;
+    use crate::config::HealthConfig;
+    use crate::source::mock::MockSource;
+
+    #[tokio::test]
+    async fn count_upstream_calls_and_errors() {
+        let health = Arc::new(Health::new(&HealthConfig::default(), 6));
+        let source = InstrumentedSource::new(Arc::new(MockSource::new()), health);
+        let calls = METRICS
+            .upstream_requests
+            .with_label_values(&["get_account"]);
+        let errors = METRICS
+            .upstream_errors
+            .with_label_values(&["get_account", "not_found"]);
+        let (calls_before, errors_before) = (calls.get(), errors.get());
+
+        assert!(source.get_account(&Pubkey::new_unique()).await.is_err());
+        source.get_slot().await.unwrap();
+
+        assert_eq!(calls.get() - calls_before, 1);
+        assert_eq!(errors.get() - errors_before, 1);
+    }
+}
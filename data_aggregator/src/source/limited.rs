@@ -0,0 +1,383 @@
+use async_trait::async_trait;
+use axum::{extract::Request, middleware::Next, response::Response};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::config::{RateLimitConfig, UPSTREAM_METHODS};
+use crate::metrics::METRICS;
+use crate::source::ChainSource;
+use crate::types::{unix_timestamp, Account, RetrievalError, Transaction};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Approximate Helius credits per call, rate_limit.method_credits overrides them
+fn default_credits(method: &str) -> u64 {
+    match method {
+        "parse_transactions" => 100,
+        "get_signatures_for_address" | "get_block" => 10,
+        _ => 1,
+    }
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // Calls made on behalf of an HTTP request
+    User,
+    // Monitor refreshes, block crawling and every other call
+    Background,
+}
+
+fn current_priority() -> Priority {
+    PRIORITY
+        .try_with(|priority| *priority)
+        .unwrap_or(Priority::Background)
+}
+
+/// Marks the upstream calls made while handling the request as user calls
+pub async fn prioritize_user_requests(request: Request, next: Next) -> Response {
+    PRIORITY.scope(Priority::User, next.run(request)).await
+}
+
+/// Refills at requests_per_sec and holds up to one second worth of tokens
+struct TokenBucket {
+    capacity: f64,
+    requests_per_sec: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(requests_per_sec: f64, now: Instant) -> Self {
+        let capacity = requests_per_sec.max(1.0);
+        TokenBucket {
+            capacity,
+            requests_per_sec,
+            tokens: capacity,
+            updated_at: now,
+        }
+    }
+
+    /// Takes a token when at least `required` tokens are available,
+    /// otherwise returns how long it takes until they are
+    fn try_take(&mut self, now: Instant, required: f64) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.requests_per_sec).min(self.capacity);
+        self.updated_at = now;
+
+        if self.tokens >= required {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (required - self.tokens) / self.requests_per_sec,
+            ))
+        }
+    }
+}
+
+/// Credits spent during the current UTC day
+struct CreditBudget {
+    daily_credits: Option<u64>,
+    // Credits background calls leave to user calls
+    user_reserve: u64,
+    day: u64,
+    consumed: u64,
+}
+
+impl CreditBudget {
+    fn try_charge(
+        &mut self,
+        day: u64,
+        credits: u64,
+        priority: Priority,
+    ) -> Result<(), RetrievalError> {
+        if day != self.day {
+            self.day = day;
+            self.consumed = 0;
+        }
+
+        if let Some(daily_credits) = self.daily_credits {
+            let available = match priority {
+                Priority::User => daily_credits,
+                Priority::Background => daily_credits.saturating_sub(self.user_reserve),
+            };
+            if self.consumed + credits > available {
+                return Err(RetrievalError::BudgetExhausted(format!(
+                    "{} of {} daily credits consumed",
+                    self.consumed, daily_credits
+                )));
+            }
+        }
+        self.consumed += credits;
+
+        Ok(())
+    }
+}
+
+/// Rate per method and daily credit budget of upstream calls, shared by the sources of
+/// every cluster. Background calls leave a share of both to user calls.
+pub struct RateLimiter {
+    buckets: HashMap<&'static str, Mutex<TokenBucket>>,
+    credits: HashMap<&'static str, u64>,
+    budget: Mutex<CreditBudget>,
+    user_reserve: f64,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let now = Instant::now();
+        let user_reserve = f64::from(config.user_reserve_percent) / 100.0;
+
+        let buckets = UPSTREAM_METHODS
+            .into_iter()
+            .map(|method| {
+                let requests_per_sec = config
+                    .method_requests_per_sec
+                    .get(method)
+                    .copied()
+                    .unwrap_or(config.requests_per_sec);
+                (method, Mutex::new(TokenBucket::new(requests_per_sec, now)))
+            })
+            .collect();
+        let credits = UPSTREAM_METHODS
+            .into_iter()
+            .map(|method| {
+                let credits = config.method_credits.get(method).copied();
+                (method, credits.unwrap_or_else(|| default_credits(method)))
+            })
+            .collect();
+        let budget = CreditBudget {
+            daily_credits: config.daily_credits,
+            user_reserve: config.daily_credits.map_or(0, |daily_credits| {
+                (daily_credits as f64 * user_reserve) as u64
+            }),
+            day: unix_timestamp() / SECS_PER_DAY,
+            consumed: 0,
+        };
+
+        RateLimiter {
+            buckets,
+            credits,
+            budget: Mutex::new(budget),
+            user_reserve,
+        }
+    }
+
+    /// Waits until the call fits the rate and charges its credits
+    async fn acquire(&self, method: &'static str) -> Result<(), RetrievalError> {
+        let priority = current_priority();
+
+        // Waits for a token, background calls leave the reserved share of the bucket to user calls
+        let bucket = &self.buckets[method];
+        loop {
+            let wait = {
+                let mut bucket = bucket.lock().unwrap();
+                let required = match priority {
+                    Priority::User => 1.0,
+                    Priority::Background => 1.0 + self.user_reserve * (bucket.capacity - 1.0),
+                };
+                match bucket.try_take(Instant::now(), required) {
+                    Ok(()) => break,
+                    Err(wait) => wait,
+                }
+            };
+            debug!(
+                method,
+                ?priority,
+                wait_ms = wait.as_millis() as u64,
+                "Upstream call throttled"
+            );
+            tokio::time::sleep(wait).await;
+        }
+
+        let credits = self.credits[method];
+        let consumed = {
+            let mut budget = self.budget.lock().unwrap();
+            budget.try_charge(unix_timestamp() / SECS_PER_DAY, credits, priority)?;
+            budget.consumed
+        };
+        METRICS
+            .upstream_credits
+            .with_label_values(&[method])
+            .inc_by(credits);
+        METRICS.upstream_credits_today.set(consumed as i64);
+
+        Ok(())
+    }
+}
+
+/// ChainSource decorator keeping upstream calls within the limits of a RateLimiter
+pub struct RateLimitedSource {
+    inner: Arc<dyn ChainSource>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedSource {
+    pub fn new(inner: Arc<dyn ChainSource>, limiter: Arc<RateLimiter>) -> Self {
+        RateLimitedSource { inner, limiter }
+    }
+
+    async fn call<T>(
+        &self,
+        method: &'static str,
+        call: impl Future<Output = Result<T, RetrievalError>>,
+    ) -> Result<T, RetrievalError> {
+        self.limiter.acquire(method).await?;
+        call.await
+    }
+}
+
+#[async_trait]
+impl ChainSource for RateLimitedSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.call("get_account", self.inner.get_account(account_pubkey))
+            .await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.call(
+            "get_multiple_accounts",
+            self.inner.get_multiple_accounts(account_pubkeys),
+        )
+        .await
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        self.call(
+            "parse_transactions",
+            self.inner.parse_transactions(tx_signatures),
+        )
+        .await
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.call(
+            "get_signatures_for_address",
+            self.inner
+                .get_signatures_for_address(account_pubkey, before, until, limit),
+        )
+        .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.call("get_slot", self.inner.get_slot()).await
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.call("get_block", self.inner.get_block(slot)).await
+    }
+}
+
+#[cfg(test)]
+mod limited_tests {
+    use super::*;
+    use crate::source::mock::MockSource;
+
+    #[test]
+    fn leave_reserved_tokens_to_user_calls() {
+        let started = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, started);
+        // 20% of the 9 tokens above the last one are reserved
+        let background = 1.0 + 0.2 * 9.0;
+
+        for _ in 0..8 {
+            bucket.try_take(started, background).unwrap();
+        }
+        let wait = bucket.try_take(started, background).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.08).abs() < 1e-9);
+
+        bucket.try_take(started, 1.0).unwrap();
+        bucket.try_take(started, 1.0).unwrap();
+        let wait = bucket.try_take(started, 1.0).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.1).abs() < 1e-9);
+
+        bucket
+            .try_take(started + Duration::from_millis(101), 1.0)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn throttle_calls_above_the_rate() {
+        let config = RateLimitConfig {
+            requests_per_sec: 20.0,
+            ..RateLimitConfig::default()
+        };
+        let limiter = Arc::new(RateLimiter::new(&config));
+        let source = RateLimitedSource::new(Arc::new(MockSource::new()), limiter);
+
+        // The bucket holds 20 tokens, the other 2 take 50ms each
+        let started = Instant::now();
+        for _ in 0..22 {
+            PRIORITY
+                .scope(Priority::User, source.get_slot())
+                .await
+                .unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn keep_daily_credits_for_user_calls() {
+        let mock = Arc::new(MockSource::new());
+        let config = RateLimitConfig {
+            requests_per_sec: 1000.0,
+            daily_credits: Some(10),
+            user_reserve_percent: 50,
+            ..RateLimitConfig::default()
+        };
+        let limiter = Arc::new(RateLimiter::new(&config));
+        let source = RateLimitedSource::new(mock.clone(), limiter.clone());
+        let consumed = METRICS.upstream_credits.with_label_values(&["get_account"]);
+        let consumed_before = consumed.get();
+
+        for _ in 0..5 {
+            assert!(source.get_account(&Pubkey::new_unique()).await.is_err());
+        }
+        let error = source.get_account(&Pubkey::new_unique()).await.unwrap_err();
+        assert_eq!(error.code(), "budget_exhausted");
+        assert_eq!(mock.account_calls(), 5);
+
+        // User calls can spend the reserve
+        for _ in 0..5 {
+            let result = PRIORITY
+                .scope(Priority::User, source.get_account(&Pubkey::new_unique()))
+                .await;
+            assert_eq!(result.unwrap_err().code(), "not_found");
+        }
+        let error = PRIORITY
+            .scope(Priority::User, source.get_account(&Pubkey::new_unique()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "budget_exhausted");
+        assert_eq!(mock.account_calls(), 10);
+        assert_eq!(consumed.get() - consumed_before, 10);
+
+        // The budget is renewed every day
+        let mut budget = limiter.budget.lock().unwrap();
+        let tomorrow = budget.day + 1;
+        budget
+            .try_charge(tomorrow, 5, Priority::Background)
+            .unwrap();
+        assert_eq!(budget.consumed, 5);
+    }
+}
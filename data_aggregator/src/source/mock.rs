@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::source::{enhanced::decode_enhanced_transaction, ChainSource};
+use crate::types::{Account, RetrievalError, Transaction};
+
+/// In-memory ChainSource used to run the aggregator offline in tests
+#[derive(Default)]
+pub struct MockSource {
+    accounts: Mutex<HashMap<Pubkey, Account>>,
+    transactions: Mutex<HashMap<String, Transaction>>,
+    // Number of get_account requests served
+    account_calls: AtomicUsize,
+    // Number of get_multiple_accounts requests served
+    multiple_accounts_calls: AtomicUsize,
+    // Number of parse_transactions requests served
+    parse_transactions_calls: AtomicUsize,
+    // Account to the signatures of its transactions, newest first
+    address_signatures: Mutex<HashMap<Pubkey, Vec<String>>>,
+    // Slot to the transactions of the block, slots without a block are skipped
+    blocks: Mutex<HashMap<u64, Vec<Transaction>>>,
+    // Slot to the error returned for its block on every request
+    block_errors: Mutex<HashMap<u64, RetrievalError>>,
+    slot: AtomicU64,
+    // Simulated upstream latency of account and transaction requests
+    delay_ms: AtomicU64,
+    // Raw enhanced transactions API payloads returned instead of the stored transactions
+    raw_transactions: Mutex<Option<Vec<Value>>>,
+    // getMultipleAccounts response returned instead of the stored accounts
+    multiple_accounts_response: Mutex<Option<Vec<Option<Account>>>>,
+    // Errors returned by the next requests instead of their response, one per request
+    faults: Mutex<VecDeque<RetrievalError>>,
+}
+
+impl MockSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_account(&self, account: Account) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .insert(account.account_pubkey, account);
+    }
+
+    pub fn remove_account(&self, account_pubkey: &Pubkey) {
+        self.accounts.lock().unwrap().remove(account_pubkey);
+    }
+
+    pub fn account_calls(&self) -> usize {
+        self.account_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn multiple_accounts_calls(&self) -> usize {
+        self.multiple_accounts_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn parse_transactions_calls(&self) -> usize {
+        self.parse_transactions_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn set_address_signatures(&self, account_pubkey: Pubkey, tx_signatures: Vec<String>) {
+        self.address_signatures
+            .lock()
+            .unwrap()
+            .insert(account_pubkey, tx_signatures);
+    }
+
+    pub fn set_block(&self, slot: u64, transactions: Vec<Transaction>) {
+        self.blocks.lock().unwrap().insert(slot, transactions);
+    }
+
+    pub fn set_block_error(&self, slot: u64, error: RetrievalError) {
+        self.block_errors.lock().unwrap().insert(slot, error);
+    }
+
+    pub fn set_slot(&self, slot: u64) {
+        self.slot.store(slot, Ordering::SeqCst);
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    async fn simulate_latency(&self) {
+        let delay_ms = self.delay_ms.load(Ordering::SeqCst);
+        if delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+    }
+
+    /// Every parse_transactions request is answered with these payloads, whatever was requested
+    pub fn set_raw_transactions(&self, payloads: Vec<Value>) {
+        *self.raw_transactions.lock().unwrap() = Some(payloads);
+    }
+
+    /// Every get_multiple_accounts request is answered with this response, whatever was requested
+    pub fn set_multiple_accounts_response(&self, accounts: Vec<Option<Account>>) {
+        *self.multiple_accounts_response.lock().unwrap() = Some(accounts);
+    }
+
+    /// The next requests fail with these errors in order, whatever the method
+    pub fn inject_faults(&self, errors: Vec<RetrievalError>) {
+        self.faults.lock().unwrap().extend(errors);
+    }
+
+    fn take_fault(&self) -> Result<(), RetrievalError> {
+        match self.faults.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_transaction(&self, transaction: Transaction) {
+        self.transactions
+            .lock()
+            .unwrap()
+            .insert(transaction.signature.clone(), transaction);
+    }
+}
+
+#[async_trait]
+impl ChainSource for MockSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.account_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        self.take_fault()?;
+        match self.accounts.lock().unwrap().get(account_pubkey) {
+            Some(account) => Ok(account.clone()),
+            None => Err(RetrievalError::NotFound(format!(
+                "Account {} not found",
+                account_pubkey
+            ))),
+        }
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.multiple_accounts_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        self.take_fault()?;
+        if let Some(response) = self.multiple_accounts_response.lock().unwrap().clone() {
+            return Ok(response);
+        }
+        let accounts = self.accounts.lock().unwrap();
+
+        Ok(account_pubkeys
+            .iter()
+            .map(|account_pubkey| accounts.get(account_pubkey).cloned())
+            .collect())
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        self.parse_transactions_calls.fetch_add(1, Ordering::SeqCst);
+        self.simulate_latency().await;
+        self.take_fault()?;
+        if let Some(payloads) = self.raw_transactions.lock().unwrap().clone() {
+            return payloads
+                .into_iter()
+                .map(decode_enhanced_transaction)
+                .collect();
+        }
+        let transactions = self.transactions.lock().unwrap();
+
+        Ok(tx_signatures
+            .iter()
+            .filter_map(|tx_signature| transactions.get(tx_signature).cloned())
+            .collect())
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.take_fault()?;
+        let address_signatures = self.address_signatures.lock().unwrap();
+        let tx_signatures = address_signatures
+            .get(account_pubkey)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let start = match before {
+            Some(before) => match tx_signatures.iter().position(|s| *s == before) {
+                Some(position) => position + 1,
+                None => tx_signatures.len(),
+            },
+            None => 0,
+        };
+
+        Ok(tx_signatures[start..]
+            .iter()
+            .take_while(|tx_signature| Some(*tx_signature) != until.as_ref())
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.take_fault()?;
+        Ok(self.slot.load(Ordering::SeqCst))
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.take_fault()?;
+        if let Some(error) = self.block_errors.lock().unwrap().get(&slot) {
+            return Err(error.clone());
+        }
+        Ok(self.blocks.lock().unwrap().get(&slot).cloned())
+    }
+}
@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use prometheus::IntGauge;
+use solana_sdk::pubkey::Pubkey;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::config::UpstreamConfig;
+use crate::metrics::METRICS;
+use crate::source::ChainSource;
+use crate::types::{Account, RetrievalError, Transaction};
+
+/// ChainSource decorator retrying transient upstream failures and failing fast while
+/// the provider is down. Every ChainSource method is a read, so all of them are retried.
+pub struct ResilientSource {
+    inner: Arc<dyn ChainSource>,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    breaker: CircuitBreaker,
+}
+
+impl ResilientSource {
+    /// `cluster` labels the circuit breaker gauge
+    pub fn new(inner: Arc<dyn ChainSource>, config: &UpstreamConfig, cluster: &str) -> Self {
+        ResilientSource {
+            inner,
+            max_retries: config.max_retries,
+            initial_backoff: Duration::from_millis(config.retry_initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.retry_max_backoff_ms),
+            breaker: CircuitBreaker::new(
+                config.breaker_failure_threshold,
+                Duration::from_secs(config.breaker_open_secs),
+                cluster,
+            ),
+        }
+    }
+
+    async fn call<T, F, Fut>(&self, method: &str, call: F) -> Result<T, RetrievalError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, RetrievalError>>,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut retries = 0;
+
+        loop {
+            self.breaker.acquire()?;
+            let result = call().await;
+            self.breaker.record(&result);
+
+            match result {
+                Err(error) if error.is_transient() && retries < self.max_retries => {
+                    // Equal jitter, retries of concurrent calls do not hit the upstream at once
+                    let delay = backoff / 2 + backoff.mul_f64(fastrand::f64() / 2.0);
+                    debug!(
+                        method,
+                        %error,
+                        retry = retries + 1,
+                        delay_ms = delay.as_millis() as u64,
+                        "Retrying upstream call"
+                    );
+                    METRICS.upstream_retries.with_label_values(&[method]).inc();
+
+                    tokio::time::sleep(delay).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    // Calls fail fast until the deadline, then one probe call is let through
+    Open { until: Instant },
+    // A probe call is in flight, another one is let through after the deadline
+    // in case it never reports back
+    HalfOpen { until: Instant },
+}
+
+/// Opens after failure_threshold transient failures in a row. While open, calls fail with
+/// UpstreamUnavailable without reaching the upstream. After open_duration a single probe
+/// call decides whether the breaker closes again or stays open for another period.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+    cluster: String,
+    open_gauge: IntGauge,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration, cluster: &str) -> Self {
+        let open_gauge = METRICS.circuit_breaker_open.with_label_values(&[cluster]);
+        open_gauge.set(0);
+
+        CircuitBreaker {
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            cluster: cluster.to_string(),
+            open_gauge,
+        }
+    }
+
+    /// Fails when the call is not allowed to reach the upstream
+    fn acquire(&self) -> Result<(), RetrievalError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } | BreakerState::HalfOpen { until } if now >= until => {
+                *state = BreakerState::HalfOpen {
+                    until: now + self.open_duration,
+                };
+                Ok(())
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => Err(
+                RetrievalError::UpstreamUnavailable("Circuit breaker is open".to_string()),
+            ),
+        }
+    }
+
+    /// Errors other than transient ones prove the upstream answered and count as a success
+    fn record<T>(&self, result: &Result<T, RetrievalError>) {
+        // Unsupported calls are refused without asking the upstream, they prove nothing
+        if matches!(result, Err(RetrievalError::Unsupported(_))) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let failed = matches!(result, Err(error) if error.is_transient());
+
+        let next = match (*state, failed) {
+            (_, false) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, true) if failures + 1 < self.failure_threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            // A late failure of a call started before the breaker opened keeps the deadline
+            (BreakerState::Open { until }, true) => BreakerState::Open { until },
+            (_, true) => BreakerState::Open {
+                until: Instant::now() + self.open_duration,
+            },
+        };
+
+        let was_open = !matches!(*state, BreakerState::Closed { .. });
+        let is_open = !matches!(next, BreakerState::Closed { .. });
+        if is_open && !was_open {
+            warn!(
+                cluster = %self.cluster,
+                open_secs = self.open_duration.as_secs(),
+                "Upstream circuit breaker opened"
+            );
+        } else if was_open && !is_open {
+            warn!(cluster = %self.cluster, "Upstream circuit breaker closed");
+        }
+        self.open_gauge.set(is_open as i64);
+
+        *state = next;
+    }
+}
+
+#[async_trait]
+impl ChainSource for ResilientSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        self.call("get_account", || self.inner.get_account(account_pubkey))
+            .await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        self.call("get_multiple_accounts", || {
+            self.inner.get_multiple_accounts(account_pubkeys)
+        })
+        .await
+    }
+
+    async fn parse_transactions(
+        &self,
+        tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        self.call("parse_transactions", || {
+            self.inner.parse_transactions(tx_signatures.clone())
+        })
+        .await
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        self.call("get_signatures_for_address", || {
+            self.inner.get_signatures_for_address(
+                account_pubkey,
+                before.clone(),
+                until.clone(),
+                limit,
+            )
+        })
+        .await
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        self.call("get_slot", || self.inner.get_slot()).await
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        self.call("get_block", || self.inner.get_block(slot)).await
+    }
+}
+
+#[cfg(test)]
+mod resilient_tests {
+    use super::*;
+    use crate::source::mock::MockSource;
+    use crate::types::unix_timestamp;
+
+    fn mock_account(account_pubkey: Pubkey) -> Account {
+        Account {
+            account_pubkey,
+            lamports: 1,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            fetched_at: unix_timestamp(),
+            context_slot: 1,
+        }
+    }
+
+    fn resilient_source(mock: Arc<MockSource>, breaker_failure_threshold: u32) -> ResilientSource {
+        let config = UpstreamConfig {
+            max_retries: 2,
+            retry_initial_backoff_ms: 1,
+            retry_max_backoff_ms: 4,
+            breaker_failure_threshold,
+            ..UpstreamConfig::default()
+        };
+        ResilientSource::new(mock, &config, "test")
+    }
+
+    #[tokio::test]
+    async fn retry_transient_errors_only() {
+        let mock = Arc::new(MockSource::new());
+        let account_pubkey = Pubkey::new_unique();
+        mock.set_account(mock_account(account_pubkey));
+        let source = resilient_source(mock.clone(), 10);
+
+        mock.inject_faults(vec![
+            RetrievalError::Timeout("slow".into()),
+            RetrievalError::RateLimited("429".into()),
+        ]);
+        assert!(source.get_account(&account_pubkey).await.is_ok());
+        assert_eq!(mock.account_calls(), 3);
+
+        // Retries are exhausted
+        mock.inject_faults(vec![RetrievalError::UpstreamUnavailable("down".into()); 3]);
+        let error = source.get_account(&account_pubkey).await.unwrap_err();
+        assert_eq!(error, RetrievalError::UpstreamUnavailable("down".into()));
+        assert_eq!(mock.account_calls(), 6);
+
+        // The upstream answered, retrying gives the same answer
+        mock.inject_faults(vec![RetrievalError::Decode("garbage".into())]);
+        let error = source.get_account(&account_pubkey).await.unwrap_err();
+        assert_eq!(error, RetrievalError::Decode("garbage".into()));
+        assert_eq!(mock.account_calls(), 7);
+    }
+
+    #[tokio::test]
+    async fn fail_fast_while_circuit_is_open() {
+        let mock = Arc::new(MockSource::new());
+        let account_pubkey = Pubkey::new_unique();
+        mock.set_account(mock_account(account_pubkey));
+        let source = resilient_source(mock.clone(), 3);
+
+        // The third failed attempt opens the breaker
+        mock.inject_faults(vec![RetrievalError::UpstreamUnavailable("down".into()); 3]);
+        assert!(source.get_account(&account_pubkey).await.is_err());
+        assert_eq!(mock.account_calls(), 3);
+
+        let error = source.get_account(&account_pubkey).await.unwrap_err();
+        assert!(error.to_string().contains("Circuit breaker is open"));
+        assert_eq!(mock.account_calls(), 3);
+    }
+
+    #[test]
+    fn close_circuit_after_successful_probe() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20), "probe");
+        let other = CircuitBreaker::new(2, Duration::from_millis(20), "other");
+        let open = |cluster: &str| {
+            METRICS
+                .circuit_breaker_open
+                .with_label_values(&[cluster])
+                .get()
+        };
+        let failure: Result<(), _> = Err(RetrievalError::Timeout("slow".into()));
+
+        breaker.acquire().unwrap();
+        breaker.record(&failure);
+        breaker.acquire().unwrap();
+        breaker.record(&failure);
+        assert!(breaker.acquire().is_err());
+        // Every cluster reports its own breaker
+        assert_eq!((open("probe"), open("other")), (1, 0));
+        other.acquire().unwrap();
+
+        // A failed probe opens the breaker for another period
+        std::thread::sleep(Duration::from_millis(25));
+        breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err(), "Only one probe is let through");
+        breaker.record(&failure);
+        assert!(breaker.acquire().is_err());
+
+        std::thread::sleep(Duration::from_millis(25));
+        breaker.acquire().unwrap();
+        breaker.record(&Ok(()));
+        breaker.acquire().unwrap();
+        breaker.acquire().unwrap();
+        assert_eq!(open("probe"), 0);
+    }
+}
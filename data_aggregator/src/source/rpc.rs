@@ -0,0 +1,314 @@
+use async_trait::async_trait;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcBlockConfig,
+    rpc_custom_error::{
+        JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED, JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+    },
+    rpc_request::RpcError,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    EncodedTransactionWithStatusMeta, TransactionDetails, UiTransactionEncoding,
+};
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::metrics::METRICS;
+use crate::source::ChainSource;
+use crate::types::{unix_timestamp, Account, RetrievalError, Transaction};
+
+fn map_account(
+    account_pubkey: &Pubkey,
+    account_data: solana_sdk::account::Account,
+    context_slot: u64,
+) -> Account {
+    Account {
+        account_pubkey: *account_pubkey,
+        owner: account_data.owner,
+        lamports: account_data.lamports,
+        executable: account_data.executable,
+        rent_epoch: account_data.rent_epoch,
+        fetched_at: unix_timestamp(),
+        context_slot,
+    }
+}
+
+fn map_block_transaction(
+    tx_with_meta: &EncodedTransactionWithStatusMeta,
+    slot: i32,
+    timestamp: u64,
+) -> Result<Transaction, RetrievalError> {
+    let transaction = tx_with_meta
+        .transaction
+        .decode()
+        .ok_or_else(|| RetrievalError::Decode("Transaction cannot be decoded".to_string()))?;
+    let signature = transaction
+        .signatures
+        .first()
+        .ok_or_else(|| RetrievalError::Decode("Transaction has no signature".to_string()))?;
+    let meta = tx_with_meta.meta.as_ref();
+    let fee = meta.map(|meta| meta.fee).unwrap_or_default();
+
+    Ok(Transaction {
+        signature: signature.to_string(),
+        timestamp,
+        // Descriptions and transfers are only provided by the enhanced transactions API
+        description: String::new(),
+        fee: i32::try_from(fee).map_err(|_| {
+            RetrievalError::Decode(format!("Fee {} of {} is out of range", fee, signature))
+        })?,
+        fee_payer: transaction
+            .message
+            .static_account_keys()
+            .first()
+            .ok_or_else(|| {
+                RetrievalError::Decode(format!("Transaction {} has no fee payer", signature))
+            })?
+            .to_string(),
+        slot,
+        transaction_error: meta
+            .and_then(|meta| meta.err.as_ref())
+            .map(|error| error.to_string()),
+        native_transfers: None,
+        ..Transaction::default()
+    })
+}
+
+fn is_skipped_slot(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                || *code == JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+    )
+}
+
+impl From<ClientError> for RetrievalError {
+    fn from(error: ClientError) -> Self {
+        let message = error.to_string();
+        match error.kind() {
+            ClientErrorKind::Reqwest(error) if error.is_timeout() => {
+                RetrievalError::Timeout(message)
+            }
+            ClientErrorKind::Reqwest(error)
+                if error.status().is_some_and(|status| status.as_u16() == 429) =>
+            {
+                RetrievalError::RateLimited(message)
+            }
+            ClientErrorKind::SerdeJson(_) | ClientErrorKind::RpcError(RpcError::ParseError(_)) => {
+                RetrievalError::Decode(message)
+            }
+            _ => RetrievalError::UpstreamUnavailable(message),
+        }
+    }
+}
+
+/// ChainSource backed by a generic Solana JSON-RPC node.
+/// Enhanced transactions are not part of the JSON-RPC API, parse_transactions always fails.
+pub struct RpcSource {
+    // Non-blocking Solana RPC client, so upstream calls never stall a tokio worker
+    rpc_client: RpcClient,
+}
+
+impl RpcSource {
+    pub fn new(url: String) -> Self {
+        RpcSource {
+            rpc_client: RpcClient::new(url),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainSource for RpcSource {
+    async fn get_account(&self, account_pubkey: &Pubkey) -> Result<Account, RetrievalError> {
+        let response = self
+            .rpc_client
+            .get_account_with_commitment(account_pubkey, self.rpc_client.commitment())
+            .await?;
+
+        match response.value {
+            Some(account_data) => Ok(map_account(
+                account_pubkey,
+                account_data,
+                response.context.slot,
+            )),
+            None => Err(RetrievalError::NotFound(format!(
+                "Account {} not found",
+                account_pubkey
+            ))),
+        }
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        account_pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RetrievalError> {
+        let response = self
+            .rpc_client
+            .get_multiple_accounts_with_commitment(account_pubkeys, self.rpc_client.commitment())
+            .await?;
+        let context_slot = response.context.slot;
+        if response.value.len() != account_pubkeys.len() {
+            return Err(RetrievalError::Decode(format!(
+                "getMultipleAccounts returned {} accounts for {} requested",
+                response.value.len(),
+                account_pubkeys.len()
+            )));
+        }
+
+        Ok(account_pubkeys
+            .iter()
+            .zip(response.value)
+            .map(|(account_pubkey, account_data)| {
+                account_data
+                    .map(|account_data| map_account(account_pubkey, account_data, context_slot))
+            })
+            .collect())
+    }
+
+    async fn parse_transactions(
+        &self,
+        _tx_signatures: Vec<String>,
+    ) -> Result<Vec<Transaction>, RetrievalError> {
+        Err(RetrievalError::Unsupported(
+            "Enhanced transactions are not provided by Solana JSON-RPC nodes".to_string(),
+        ))
+    }
+
+    async fn get_signatures_for_address(
+        &self,
+        account_pubkey: &Pubkey,
+        before: Option<String>,
+        until: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<String>, RetrievalError> {
+        let parse_signature = |signature: Option<String>| {
+            signature
+                .as_deref()
+                .map(Signature::from_str)
+                .transpose()
+                .map_err(|error| RetrievalError::InvalidInput(error.to_string()))
+        };
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before: parse_signature(before)?,
+            until: parse_signature(until)?,
+            limit: Some(limit),
+            commitment: None,
+        };
+
+        let signatures = self
+            .rpc_client
+            .get_signatures_for_address_with_config(account_pubkey, config)
+            .await?;
+
+        Ok(signatures
+            .into_iter()
+            .map(|signature| signature.signature)
+            .collect())
+    }
+
+    async fn get_slot(&self) -> Result<u64, RetrievalError> {
+        Ok(self.rpc_client.get_slot().await?)
+    }
+
+    async fn get_block(&self, slot: u64) -> Result<Option<Vec<Transaction>>, RetrievalError> {
+        let config = RpcBlockConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            transaction_details: Some(TransactionDetails::Full),
+            rewards: Some(false),
+            commitment: None,
+            max_supported_transaction_version: Some(0),
+        };
+
+        let block = match self.rpc_client.get_block_with_config(slot, config).await {
+            Ok(block) => block,
+            Err(error) if is_skipped_slot(&error) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let timestamp = block.block_time.unwrap_or_default().max(0) as u64;
+        let slot = i32::try_from(slot)
+            .map_err(|_| RetrievalError::Decode(format!("Slot {} is out of range", slot)))?;
+
+        // A transaction that cannot be mapped does not fail the rest of the block
+        let mut transactions = vec![];
+        for (index, tx_with_meta) in block.transactions.unwrap_or_default().iter().enumerate() {
+            match map_block_transaction(tx_with_meta, slot, timestamp) {
+                Ok(transaction) => transactions.push(transaction),
+                Err(error) => {
+                    warn!(slot, index, %error, "Block transaction dropped");
+                    METRICS.dropped_block_transactions.inc();
+                }
+            }
+        }
+
+        Ok(Some(transactions))
+    }
+}
+
+#[cfg(test)]
+mod rpc_tests {
+    use solana_sdk::{
+        hash::Hash, signature::Keypair, signer::Signer, system_instruction,
+        transaction::Transaction as SdkTransaction,
+    };
+    use solana_transaction_status::{
+        Encodable, EncodedTransaction, TransactionBinaryEncoding, TransactionStatusMeta,
+    };
+
+    use super::*;
+
+    fn block_transaction(fee: u64) -> (String, EncodedTransactionWithStatusMeta) {
+        let payer = Keypair::new();
+        let transaction = SdkTransaction::new_signed_with_payer(
+            &[system_instruction::transfer(
+                &payer.pubkey(),
+                &Pubkey::new_unique(),
+                1,
+            )],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        let tx_with_meta = EncodedTransactionWithStatusMeta {
+            transaction: transaction.encode(UiTransactionEncoding::Base64),
+            meta: Some(
+                TransactionStatusMeta {
+                    fee,
+                    ..TransactionStatusMeta::default()
+                }
+                .into(),
+            ),
+            version: None,
+        };
+
+        (transaction.signatures[0].to_string(), tx_with_meta)
+    }
+
+    #[test]
+    fn map_or_reject_block_transactions() {
+        let (signature, tx_with_meta) = block_transaction(5000);
+        let transaction = map_block_transaction(&tx_with_meta, 10, 1720605742).unwrap();
+        assert_eq!(transaction.signature, signature);
+        assert_eq!(transaction.fee, 5000);
+        assert_eq!(transaction.slot, 10);
+
+        let (_, tx_with_meta) = block_transaction(u64::MAX);
+        let error = map_block_transaction(&tx_with_meta, 10, 1720605742).unwrap_err();
+        assert_eq!(error.code(), "decode_error");
+
+        let undecodable = EncodedTransactionWithStatusMeta {
+            transaction: EncodedTransaction::Binary(
+                "not base64".to_string(),
+                TransactionBinaryEncoding::Base64,
+            ),
+            meta: None,
+            version: None,
+        };
+        let error = map_block_transaction(&undecodable, 10, 1720605742).unwrap_err();
+        assert_eq!(error.code(), "decode_error");
+    }
+}
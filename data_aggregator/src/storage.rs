@@ -0,0 +1,62 @@
+use anyhow::Error;
+
+use crate::types::{Account, Transaction};
+
+mod bounded;
+mod memory;
+mod sqlite;
+
+pub use self::bounded::{BoundedStorage, CacheStats};
+pub use self::memory::MemoryDatabase;
+pub use self::sqlite::SqliteDatabase;
+
+/// Storage is the cache backend used by Retrieval to keep accounts and transactions.
+/// Accounts are keyed by the account's public key and transactions by their signature.
+/// It also keeps the checkpoints of background jobs so they can resume after a restart.
+pub trait Storage: Send + Sync {
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, Error>;
+
+    /// Keeps the stored account when it was read at a newer slot,
+    /// returns whether the account was stored
+    fn put_account(&self, account_id: &str, account: &Account) -> Result<bool, Error>;
+
+    // Not used by the server, /account checks the age of the cached account instead
+    #[allow(dead_code)]
+    fn delete_account(&self, account_id: &str) -> Result<(), Error>;
+
+    // Existence checks are only used by the tests, the server reads the entries directly
+    #[cfg(test)]
+    fn account_exists(&self, account_id: &str) -> Result<bool, Error>;
+
+    fn account_count(&self) -> Result<usize, Error>;
+
+    fn list_accounts(&self) -> Result<Vec<Account>, Error>;
+
+    fn get_transaction(&self, tx_signature: &str) -> Result<Option<Transaction>, Error>;
+
+    fn put_transaction(&self, tx_signature: &str, transaction: &Transaction) -> Result<(), Error>;
+
+    fn delete_transaction(&self, tx_signature: &str) -> Result<(), Error>;
+
+    #[cfg(test)]
+    fn transaction_exists(&self, tx_signature: &str) -> Result<bool, Error>;
+
+    fn transaction_count(&self) -> Result<usize, Error>;
+
+    fn list_transactions(&self) -> Result<Vec<Transaction>, Error>;
+
+    /// Last slot processed by a named background job, e.g. the block crawler
+    fn get_checkpoint(&self, name: &str) -> Result<Option<u64>, Error>;
+
+    fn put_checkpoint(&self, name: &str, slot: u64) -> Result<(), Error>;
+
+    /// Writes buffered data to durable storage, called once on shutdown
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Hit, miss and eviction counters, None when the storage is not bounded
+    fn cache_stats(&self) -> Result<Option<CacheStats>, Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,496 @@
+use anyhow::Error;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::config::StorageConfig;
+use crate::storage::Storage;
+use crate::types::{Account, Transaction};
+
+/// Snapshot of the cache counters
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub accounts: usize,
+    pub transactions: usize,
+    // Approximate size of the evictable entries, watched accounts are not counted
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Account,
+    Transaction,
+}
+
+#[derive(Debug)]
+struct Entry {
+    // Position in the recency order, a higher tick was used more recently
+    tick: u64,
+    last_used: Instant,
+    bytes: usize,
+}
+
+/// Recency order of the cached entries, the front of each order is evicted first
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<(Kind, String), Entry>,
+    accounts: BTreeMap<u64, String>,
+    transactions: BTreeMap<u64, String>,
+    bytes: usize,
+    next_tick: u64,
+}
+
+impl Lru {
+    fn order(&mut self, kind: Kind) -> &mut BTreeMap<u64, String> {
+        match kind {
+            Kind::Account => &mut self.accounts,
+            Kind::Transaction => &mut self.transactions,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    /// Marks an entry as used, watched accounts are not tracked
+    fn touch(&mut self, kind: Kind, key: &str) {
+        let tick = self.tick();
+        let Some(entry) = self.entries.get_mut(&(kind, key.to_string())) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut entry.tick, tick);
+        entry.last_used = Instant::now();
+
+        let order = self.order(kind);
+        order.remove(&previous);
+        order.insert(tick, key.to_string());
+    }
+
+    /// Tracks a new entry as just used, updating an existing one keeps its recency.
+    /// Background refreshes therefore do not keep entries nobody asks for in the cache.
+    fn insert(&mut self, kind: Kind, key: &str, bytes: usize) {
+        if let Some(entry) = self.entries.get_mut(&(kind, key.to_string())) {
+            self.bytes = self.bytes - entry.bytes + bytes;
+            entry.bytes = bytes;
+            return;
+        }
+
+        let tick = self.tick();
+        self.entries.insert(
+            (kind, key.to_string()),
+            Entry {
+                tick,
+                last_used: Instant::now(),
+                bytes,
+            },
+        );
+        self.order(kind).insert(tick, key.to_string());
+        self.bytes += bytes;
+    }
+
+    fn remove(&mut self, kind: Kind, key: &str) {
+        if let Some(entry) = self.entries.remove(&(kind, key.to_string())) {
+            self.order(kind).remove(&entry.tick);
+            self.bytes -= entry.bytes;
+        }
+    }
+
+    fn oldest(&self, kind: Kind) -> Option<(u64, &String)> {
+        let order = match kind {
+            Kind::Account => &self.accounts,
+            Kind::Transaction => &self.transactions,
+        };
+        order.first_key_value().map(|(tick, key)| (*tick, key))
+    }
+
+    fn is_expired(&self, kind: Kind, key: &str, ttl: Option<Duration>) -> bool {
+        match (ttl, self.entries.get(&(kind, key.to_string()))) {
+            (Some(ttl), Some(entry)) => entry.last_used.elapsed() > ttl,
+            _ => false,
+        }
+    }
+}
+
+/// Storage decorator that bounds the cache by entry count, approximate bytes and idle time.
+/// The least recently used entries are evicted first, watched accounts are never evicted.
+pub struct BoundedStorage {
+    inner: Box<dyn Storage>,
+    max_accounts: Option<usize>,
+    max_transactions: Option<usize>,
+    max_bytes: Option<usize>,
+    // Entries not used for this long are evicted
+    ttl: Option<Duration>,
+    watched_accounts: HashSet<String>,
+    // Only bookkeeping happens under the lock, the inner storage is called outside of it
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl BoundedStorage {
+    pub fn new(inner: Box<dyn Storage>, config: &StorageConfig) -> Result<Self, Error> {
+        let storage = BoundedStorage {
+            inner,
+            max_accounts: config.max_accounts,
+            max_transactions: config.max_transactions,
+            max_bytes: config.max_bytes,
+            ttl: config.ttl_secs.map(Duration::from_secs),
+            watched_accounts: config.watched_accounts.iter().cloned().collect(),
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        };
+
+        // Entries persisted by a previous run are tracked in storage order
+        for account in storage.inner.list_accounts()? {
+            let account_id = account.account_pubkey.to_string();
+            storage.track(Kind::Account, &account_id, &account)?;
+        }
+        for transaction in storage.inner.list_transactions()? {
+            storage.track(Kind::Transaction, &transaction.signature, &transaction)?;
+        }
+        storage.evict()?;
+
+        Ok(storage)
+    }
+
+    fn track<T: Serialize>(&self, kind: Kind, key: &str, value: &T) -> Result<(), Error> {
+        if kind == Kind::Account && self.watched_accounts.contains(key) {
+            return Ok(());
+        }
+
+        // The JSON size is a good enough approximation of the memory used by an entry
+        let bytes = serde_json::to_string(value)?.len();
+        self.lru.lock().unwrap().insert(kind, key, bytes);
+        Ok(())
+    }
+
+    fn record_lookup(&self, found: bool) {
+        match found {
+            true => self.hits.fetch_add(1, Ordering::Relaxed),
+            false => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Evicts the entry when it was not used within the TTL, returns whether it expired
+    fn expire(&self, kind: Kind, key: &str) -> Result<bool, Error> {
+        let expired = {
+            let mut lru = self.lru.lock().unwrap();
+            let expired = lru.is_expired(kind, key, self.ttl);
+            if expired {
+                lru.remove(kind, key);
+            }
+            expired
+        };
+
+        if expired {
+            self.delete(kind, key)?;
+        }
+        Ok(expired)
+    }
+
+    /// Marks a looked up entry as used, expired entries are evicted and reported as missing
+    fn use_entry(&self, kind: Kind, key: &str) -> Result<bool, Error> {
+        if self.expire(kind, key)? {
+            return Ok(false);
+        }
+
+        self.lru.lock().unwrap().touch(kind, key);
+        Ok(true)
+    }
+
+    fn delete(&self, kind: Kind, key: &str) -> Result<(), Error> {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+        match kind {
+            Kind::Account => self.inner.delete_account(key),
+            Kind::Transaction => self.inner.delete_transaction(key),
+        }
+    }
+
+    /// Picks the entries over the limits and removes them from the inner storage
+    fn evict(&self) -> Result<(), Error> {
+        let evicted = {
+            let mut lru = self.lru.lock().unwrap();
+            let mut evicted = vec![];
+
+            loop {
+                let expired = |lru: &Lru, kind| {
+                    lru.oldest(kind)
+                        .is_some_and(|(_, key)| lru.is_expired(kind, key, self.ttl))
+                };
+                let over = |count: usize, max: Option<usize>| max.is_some_and(|max| count > max);
+
+                let kind = if expired(&lru, Kind::Account)
+                    || over(lru.accounts.len(), self.max_accounts)
+                {
+                    Kind::Account
+                } else if expired(&lru, Kind::Transaction)
+                    || over(lru.transactions.len(), self.max_transactions)
+                {
+                    Kind::Transaction
+                } else if over(lru.bytes, self.max_bytes) {
+                    // Over the byte limit the least recently used entry of both kinds goes first
+                    match (lru.oldest(Kind::Account), lru.oldest(Kind::Transaction)) {
+                        (Some((account, _)), Some((transaction, _))) if account < transaction => {
+                            Kind::Account
+                        }
+                        (_, Some(_)) => Kind::Transaction,
+                        (Some(_), None) => Kind::Account,
+                        (None, None) => break,
+                    }
+                } else {
+                    break;
+                };
+
+                let Some(key) = lru.oldest(kind).map(|(_, key)| key.clone()) else {
+                    break;
+                };
+                lru.remove(kind, &key);
+                evicted.push((kind, key));
+            }
+
+            evicted
+        };
+
+        for (kind, key) in evicted {
+            self.delete(kind, &key)?;
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, Error> {
+        Ok(CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            accounts: self.inner.account_count()?,
+            transactions: self.inner.transaction_count()?,
+            bytes: self.lru.lock().unwrap().bytes,
+        })
+    }
+}
+
+impl Storage for BoundedStorage {
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, Error> {
+        let mut account = self.inner.get_account(account_id)?;
+        if account.is_some() && !self.use_entry(Kind::Account, account_id)? {
+            account = None;
+        }
+
+        self.record_lookup(account.is_some());
+        Ok(account)
+    }
+
+    fn put_account(&self, account_id: &str, account: &Account) -> Result<bool, Error> {
+        if !self.inner.put_account(account_id, account)? {
+            return Ok(false);
+        }
+        self.track(Kind::Account, account_id, account)?;
+        self.evict()?;
+        Ok(true)
+    }
+
+    fn delete_account(&self, account_id: &str) -> Result<(), Error> {
+        self.lru.lock().unwrap().remove(Kind::Account, account_id);
+        self.inner.delete_account(account_id)
+    }
+
+    #[cfg(test)]
+    fn account_exists(&self, account_id: &str) -> Result<bool, Error> {
+        self.inner.account_exists(account_id)
+    }
+
+    fn account_count(&self) -> Result<usize, Error> {
+        self.inner.account_count()
+    }
+
+    fn list_accounts(&self) -> Result<Vec<Account>, Error> {
+        self.inner.list_accounts()
+    }
+
+    fn get_transaction(&self, tx_signature: &str) -> Result<Option<Transaction>, Error> {
+        let mut transaction = self.inner.get_transaction(tx_signature)?;
+        if transaction.is_some() && !self.use_entry(Kind::Transaction, tx_signature)? {
+            transaction = None;
+        }
+
+        self.record_lookup(transaction.is_some());
+        Ok(transaction)
+    }
+
+    fn put_transaction(&self, tx_signature: &str, transaction: &Transaction) -> Result<(), Error> {
+        self.inner.put_transaction(tx_signature, transaction)?;
+        self.track(Kind::Transaction, tx_signature, transaction)?;
+        self.evict()
+    }
+
+    fn delete_transaction(&self, tx_signature: &str) -> Result<(), Error> {
+        self.lru
+            .lock()
+            .unwrap()
+            .remove(Kind::Transaction, tx_signature);
+        self.inner.delete_transaction(tx_signature)
+    }
+
+    #[cfg(test)]
+    fn transaction_exists(&self, tx_signature: &str) -> Result<bool, Error> {
+        Ok(self.inner.transaction_exists(tx_signature)?
+            && !self.expire(Kind::Transaction, tx_signature)?)
+    }
+
+    fn transaction_count(&self) -> Result<usize, Error> {
+        self.inner.transaction_count()
+    }
+
+    fn list_transactions(&self) -> Result<Vec<Transaction>, Error> {
+        self.inner.list_transactions()
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<Option<u64>, Error> {
+        self.inner.get_checkpoint(name)
+    }
+
+    fn put_checkpoint(&self, name: &str, slot: u64) -> Result<(), Error> {
+        self.inner.put_checkpoint(name, slot)
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+
+    fn cache_stats(&self) -> Result<Option<CacheStats>, Error> {
+        self.stats().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod bounded_tests {
+    use solana_sdk::pubkey::Pubkey;
+    use std::time::Duration;
+
+    use crate::config::StorageConfig;
+    use crate::storage::{BoundedStorage, MemoryDatabase, Storage};
+    use crate::types::{Account, Transaction};
+
+    fn account(account_pubkey: Pubkey) -> Account {
+        Account {
+            account_pubkey,
+            lamports: 1,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            fetched_at: 0,
+            context_slot: 0,
+        }
+    }
+
+    fn bounded(config: StorageConfig) -> BoundedStorage {
+        BoundedStorage::new(Box::new(MemoryDatabase::new()), &config).unwrap()
+    }
+
+    fn put(storage: &BoundedStorage, account_pubkey: Pubkey) -> String {
+        let account_id = account_pubkey.to_string();
+        storage
+            .put_account(&account_id, &account(account_pubkey))
+            .unwrap();
+        account_id
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let storage = bounded(StorageConfig {
+            max_accounts: Some(2),
+            ..StorageConfig::default()
+        });
+
+        let first = put(&storage, Pubkey::new_unique());
+        let second = put(&storage, Pubkey::new_unique());
+        assert!(storage.get_account(&first).unwrap().is_some());
+        // Refreshing an account does not count as a use
+        put(&storage, second.parse().unwrap());
+        let third = put(&storage, Pubkey::new_unique());
+
+        assert!(storage.get_account(&second).unwrap().is_none());
+        assert!(storage.get_account(&first).unwrap().is_some());
+        assert!(storage.get_account(&third).unwrap().is_some());
+
+        let stats = storage.cache_stats().unwrap().unwrap();
+        assert_eq!(stats.accounts, 2);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+    }
+
+    #[test]
+    fn keep_watched_accounts() {
+        let watched = Pubkey::new_unique();
+        let storage = bounded(StorageConfig {
+            max_accounts: Some(1),
+            watched_accounts: vec![watched.to_string()],
+            ..StorageConfig::default()
+        });
+
+        let watched_id = put(&storage, watched);
+        let first = put(&storage, Pubkey::new_unique());
+        let second = put(&storage, Pubkey::new_unique());
+
+        assert!(storage.get_account(&watched_id).unwrap().is_some());
+        assert!(storage.get_account(&first).unwrap().is_none());
+        assert!(storage.get_account(&second).unwrap().is_some());
+        assert_eq!(storage.account_count().unwrap(), 2);
+    }
+
+    #[test]
+    fn evict_over_byte_limit() {
+        let transaction = |signature: &str| Transaction {
+            signature: signature.to_string(),
+            description: "x".repeat(1000),
+            ..Transaction::default()
+        };
+        // Room for two and a half transactions
+        let size = serde_json::to_string(&transaction("a")).unwrap().len();
+        let storage = bounded(StorageConfig {
+            max_bytes: Some(size * 5 / 2),
+            ..StorageConfig::default()
+        });
+
+        for signature in ["a", "b", "c"] {
+            storage
+                .put_transaction(signature, &transaction(signature))
+                .unwrap();
+        }
+
+        assert!(!storage.transaction_exists("a").unwrap());
+        assert!(storage.transaction_exists("c").unwrap());
+        let stats = storage.cache_stats().unwrap().unwrap();
+        assert_eq!(stats.transactions, 2);
+        assert_eq!(stats.bytes, size * 2);
+    }
+
+    #[test]
+    fn expire_idle_entries() {
+        let mut storage = bounded(StorageConfig::default());
+        storage.ttl = Some(Duration::from_millis(20));
+
+        let idle = put(&storage, Pubkey::new_unique());
+        std::thread::sleep(Duration::from_millis(30));
+        let fresh = put(&storage, Pubkey::new_unique());
+
+        assert!(storage.get_account(&fresh).unwrap().is_some());
+        assert!(storage.get_account(&idle).unwrap().is_none());
+        assert!(!storage.account_exists(&idle).unwrap());
+        assert_eq!(storage.cache_stats().unwrap().unwrap().evictions, 1);
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Error;
+use dashmap::{mapref::entry::Entry, DashMap};
+
+use crate::storage::Storage;
+use crate::types::{Account, Transaction};
+
+/// Default in-memory Storage, the cache is lost on restart.
+/// Sharded maps lock only the shard of a key, so reads are not blocked by writes of other keys.
+#[derive(Debug, Default)]
+pub struct MemoryDatabase {
+    // Account's public key as a string is the hashmap key for account data
+    accounts: DashMap<String, Account>,
+    // The signature as a string serves as the hashmap key for transaction data
+    transactions: DashMap<String, Transaction>,
+    // Background job name to its last processed slot
+    checkpoints: DashMap<String, u64>,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryDatabase {
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, Error> {
+        Ok(self
+            .accounts
+            .get(account_id)
+            .map(|account| account.value().clone()))
+    }
+
+    fn put_account(&self, account_id: &str, account: &Account) -> Result<bool, Error> {
+        // The shard stays locked between the comparison and the write
+        match self.accounts.entry(account_id.to_string()) {
+            Entry::Occupied(entry) if entry.get().context_slot > account.context_slot => {
+                return Ok(false)
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(account.clone());
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(account.clone());
+            }
+        }
+        Ok(true)
+    }
+
+    fn delete_account(&self, account_id: &str) -> Result<(), Error> {
+        self.accounts.remove(account_id);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn account_exists(&self, account_id: &str) -> Result<bool, Error> {
+        Ok(self.accounts.contains_key(account_id))
+    }
+
+    fn account_count(&self) -> Result<usize, Error> {
+        Ok(self.accounts.len())
+    }
+
+    fn list_accounts(&self) -> Result<Vec<Account>, Error> {
+        Ok(self
+            .accounts
+            .iter()
+            .map(|account| account.value().clone())
+            .collect())
+    }
+
+    fn get_transaction(&self, tx_signature: &str) -> Result<Option<Transaction>, Error> {
+        Ok(self
+            .transactions
+            .get(tx_signature)
+            .map(|transaction| transaction.value().clone()))
+    }
+
+    fn put_transaction(&self, tx_signature: &str, transaction: &Transaction) -> Result<(), Error> {
+        self.transactions
+            .insert(tx_signature.to_string(), transaction.clone());
+        Ok(())
+    }
+
+    fn delete_transaction(&self, tx_signature: &str) -> Result<(), Error> {
+        self.transactions.remove(tx_signature);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn transaction_exists(&self, tx_signature: &str) -> Result<bool, Error> {
+        Ok(self.transactions.contains_key(tx_signature))
+    }
+
+    fn transaction_count(&self) -> Result<usize, Error> {
+        Ok(self.transactions.len())
+    }
+
+    fn list_transactions(&self) -> Result<Vec<Transaction>, Error> {
+        Ok(self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.value().clone())
+            .collect())
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<Option<u64>, Error> {
+        Ok(self.checkpoints.get(name).map(|slot| *slot.value()))
+    }
+
+    fn put_checkpoint(&self, name: &str, slot: u64) -> Result<(), Error> {
+        self.checkpoints.insert(name.to_string(), slot);
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::Error;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{path::Path, sync::Mutex};
+
+use crate::storage::Storage;
+use crate::types::{Account, Transaction};
+
+/// Embedded SQLite Storage, the cache survives restarts.
+/// Records are stored as JSON documents keyed by account id or signature.
+pub struct SqliteDatabase {
+    // rusqlite::Connection is not Sync, so access is serialized with a mutex
+    connection: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, Error> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                account_id TEXT PRIMARY KEY NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS transactions (
+                signature TEXT PRIMARY KEY NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS checkpoints (
+                name TEXT PRIMARY KEY NOT NULL,
+                slot INTEGER NOT NULL
+            );",
+        )?;
+
+        Ok(SqliteDatabase {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn get<T: DeserializeOwned>(&self, sql: &str, key: &str) -> Result<Option<T>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let data: Option<String> = connection
+            .query_row(sql, params![key], |row| row.get(0))
+            .optional()?;
+
+        match data {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put<T: Serialize>(&self, sql: &str, key: &str, value: &T) -> Result<(), Error> {
+        let data = serde_json::to_string(value)?;
+        let connection = self.connection.lock().unwrap();
+        connection.execute(sql, params![key, data])?;
+        Ok(())
+    }
+
+    fn delete(&self, sql: &str, key: &str) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(sql, params![key])?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn exists(&self, sql: &str, key: &str) -> Result<bool, Error> {
+        let connection = self.connection.lock().unwrap();
+        let exists = connection
+            .query_row(sql, params![key], |_| Ok(()))
+            .optional()?
+            .is_some();
+        Ok(exists)
+    }
+
+    fn count(&self, sql: &str) -> Result<usize, Error> {
+        let connection = self.connection.lock().unwrap();
+        let count: i64 = connection.query_row(sql, [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    fn list<T: DeserializeOwned>(&self, sql: &str) -> Result<Vec<T>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+
+        let mut values = vec![];
+        for data in rows {
+            values.push(serde_json::from_str(&data?)?);
+        }
+        Ok(values)
+    }
+}
+
+impl Storage for SqliteDatabase {
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, Error> {
+        self.get(
+            "SELECT data FROM accounts WHERE account_id = ?1",
+            account_id,
+        )
+    }
+
+    fn put_account(&self, account_id: &str, account: &Account) -> Result<bool, Error> {
+        let data = serde_json::to_string(account)?;
+        let connection = self.connection.lock().unwrap();
+        // The row is only replaced by an account read at the same or a newer slot
+        let changed = connection.execute(
+            "INSERT INTO accounts (account_id, data) VALUES (?1, ?2)
+            ON CONFLICT (account_id) DO UPDATE SET data = excluded.data
+            WHERE json_extract(excluded.data, '$.context_slot')
+                >= COALESCE(json_extract(accounts.data, '$.context_slot'), 0)",
+            params![account_id, data],
+        )?;
+        Ok(changed > 0)
+    }
+
+    fn delete_account(&self, account_id: &str) -> Result<(), Error> {
+        self.delete("DELETE FROM accounts WHERE account_id = ?1", account_id)
+    }
+
+    #[cfg(test)]
+    fn account_exists(&self, account_id: &str) -> Result<bool, Error> {
+        self.exists("SELECT 1 FROM accounts WHERE account_id = ?1", account_id)
+    }
+
+    fn account_count(&self) -> Result<usize, Error> {
+        self.count("SELECT COUNT(*) FROM accounts")
+    }
+
+    fn list_accounts(&self) -> Result<Vec<Account>, Error> {
+        self.list("SELECT data FROM accounts ORDER BY account_id")
+    }
+
+    fn get_transaction(&self, tx_signature: &str) -> Result<Option<Transaction>, Error> {
+        self.get(
+            "SELECT data FROM transactions WHERE signature = ?1",
+            tx_signature,
+        )
+    }
+
+    fn put_transaction(&self, tx_signature: &str, transaction: &Transaction) -> Result<(), Error> {
+        self.put(
+            "INSERT OR REPLACE INTO transactions (signature, data) VALUES (?1, ?2)",
+            tx_signature,
+            transaction,
+        )
+    }
+
+    fn delete_transaction(&self, tx_signature: &str) -> Result<(), Error> {
+        self.delete(
+            "DELETE FROM transactions WHERE signature = ?1",
+            tx_signature,
+        )
+    }
+
+    #[cfg(test)]
+    fn transaction_exists(&self, tx_signature: &str) -> Result<bool, Error> {
+        self.exists(
+            "SELECT 1 FROM transactions WHERE signature = ?1",
+            tx_signature,
+        )
+    }
+
+    fn transaction_count(&self) -> Result<usize, Error> {
+        self.count("SELECT COUNT(*) FROM transactions")
+    }
+
+    fn list_transactions(&self) -> Result<Vec<Transaction>, Error> {
+        self.list("SELECT data FROM transactions ORDER BY signature")
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<Option<u64>, Error> {
+        let connection = self.connection.lock().unwrap();
+        let slot: Option<i64> = connection
+            .query_row(
+                "SELECT slot FROM checkpoints WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(slot.map(|slot| slot as u64))
+    }
+
+    fn put_checkpoint(&self, name: &str, slot: u64) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO checkpoints (name, slot) VALUES (?1, ?2)",
+            params![name, slot as i64],
+        )?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        // Writes dirty pages held in the page cache of open transactions
+        self.connection.lock().unwrap().cache_flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod sqlite_tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::storage::{SqliteDatabase, Storage};
+    use crate::types::{unix_timestamp, Account, NativeTransfer, Transaction};
+
+    fn account(account_pubkey: Pubkey, lamports: u64) -> Account {
+        Account {
+            account_pubkey,
+            lamports,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            fetched_at: unix_timestamp(),
+            context_slot: 1,
+        }
+    }
+
+    #[test]
+    fn put_and_get_account() {
+        let database = SqliteDatabase::open_in_memory().unwrap();
+        let account_pubkey = Pubkey::new_unique();
+        let account_id = account_pubkey.to_string();
+
+        assert!(!database.account_exists(&account_id).unwrap());
+        assert!(database.get_account(&account_id).unwrap().is_none());
+
+        database
+            .put_account(&account_id, &account(account_pubkey, 1))
+            .unwrap();
+        database
+            .put_account(&account_id, &account(account_pubkey, 2))
+            .unwrap();
+
+        assert!(database.account_exists(&account_id).unwrap());
+        assert_eq!(database.account_count().unwrap(), 1);
+        let stored = database.get_account(&account_id).unwrap().unwrap();
+        assert_eq!(stored.account_pubkey, account_pubkey);
+        assert_eq!(stored.lamports, 2);
+        assert_eq!(stored.rent_epoch, u64::MAX);
+        assert_eq!(database.list_accounts().unwrap().len(), 1);
+
+        // An account read at an older slot does not replace the stored one
+        let newer = Account {
+            context_slot: 5,
+            ..account(account_pubkey, 3)
+        };
+        assert!(database.put_account(&account_id, &newer).unwrap());
+        assert!(!database
+            .put_account(&account_id, &account(account_pubkey, 4))
+            .unwrap());
+        assert_eq!(
+            database.get_account(&account_id).unwrap().unwrap().lamports,
+            3
+        );
+    }
+
+    #[test]
+    fn put_and_get_transaction() {
+        let database = SqliteDatabase::open_in_memory().unwrap();
+        let transaction = Transaction {
+            signature: "signature".to_string(),
+            timestamp: 1720605742,
+            description: "".to_string(),
+            fee: 5001,
+            fee_payer: "fee_payer".to_string(),
+            slot: 276738369,
+            native_transfers: Some(vec![NativeTransfer {
+                amount: 2039280,
+                from_user_account: None,
+                to_user_account: Some("to".to_string()),
+            }]),
+            ..Transaction::default()
+        };
+
+        database
+            .put_transaction(&transaction.signature, &transaction)
+            .unwrap();
+
+        assert!(database.transaction_exists("signature").unwrap());
+        assert_eq!(database.transaction_count().unwrap(), 1);
+        let stored = database.get_transaction("signature").unwrap().unwrap();
+        assert_eq!(stored.fee, 5001);
+        assert_eq!(stored.native_transfers.unwrap()[0].amount, 2039280);
+        assert_eq!(database.list_transactions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn cache_survives_reopen() {
+        let path =
+            std::env::temp_dir().join(format!("data_aggregator_{}.sqlite", Pubkey::new_unique()));
+        let account_pubkey = Pubkey::new_unique();
+
+        {
+            let database = SqliteDatabase::open(&path).unwrap();
+            database
+                .put_account(&account_pubkey.to_string(), &account(account_pubkey, 7))
+                .unwrap();
+        }
+
+        let database = SqliteDatabase::open(&path).unwrap();
+        let stored = database
+            .get_account(&account_pubkey.to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.lamports, 7);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+use anyhow::Error;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpStream, time::interval};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::types::{unix_timestamp, Account, DataAggregator};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Reconnect delay doubles after every failed attempt, up to the maximum
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Shared state of the WebSocket subscriptions, used by server_monitor
+/// to fall back to polling while the socket is unavailable
+#[derive(Debug, Default)]
+pub struct SubscriptionStatus {
+    connected: AtomicBool,
+    // Latest slot received from slotSubscribe
+    slot: AtomicU64,
+    // Slot seen by the previous slot_advanced call
+    checked_slot: AtomicU64,
+}
+
+impl SubscriptionStatus {
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn slot(&self) -> u64 {
+        self.slot.load(Ordering::SeqCst)
+    }
+
+    /// Whether a slot update arrived since the previous call
+    pub fn slot_advanced(&self) -> bool {
+        let slot = self.slot();
+        self.checked_slot.swap(slot, Ordering::SeqCst) < slot
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcMessage {
+    id: Option<u64>,
+    result: Option<Value>,
+    error: Option<Value>,
+    method: Option<String>,
+    params: Option<NotificationParams>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NotificationParams {
+    subscription: u64,
+    result: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountNotification {
+    context: NotificationContext,
+    value: AccountValue,
+}
+
+#[derive(Debug, Deserialize)]
+struct NotificationContext {
+    slot: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountValue {
+    lamports: u64,
+    owner: String,
+    executable: bool,
+    rent_epoch: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlotNotification {
+    slot: u64,
+}
+
+/// Subscription bookkeeping of a single WebSocket connection
+#[derive(Default)]
+struct Session {
+    next_request_id: u64,
+    slot_request_id: Option<u64>,
+    // Subscribe requests waiting for the subscription id
+    pending: HashMap<u64, Pubkey>,
+    // Subscription id to the subscribed account
+    subscriptions: HashMap<u64, Pubkey>,
+    // Accounts with a pending or active subscription
+    subscribed: HashSet<Pubkey>,
+}
+
+impl Session {
+    fn request(&mut self, method: &str, params: Value) -> (u64, Message) {
+        self.next_request_id += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_request_id,
+            "method": method,
+            "params": params,
+        });
+
+        (self.next_request_id, Message::Text(request.to_string()))
+    }
+
+    async fn subscribe_slot(&mut self, socket: &mut Socket) -> Result<(), Error> {
+        let (request_id, message) = self.request("slotSubscribe", json!([]));
+        self.slot_request_id = Some(request_id);
+        socket.send(message).await?;
+        Ok(())
+    }
+
+    async fn subscribe_accounts(
+        &mut self,
+        socket: &mut Socket,
+        account_pubkeys: Vec<Pubkey>,
+    ) -> Result<(), Error> {
+        for account_pubkey in account_pubkeys {
+            if !self.subscribed.insert(account_pubkey) {
+                continue;
+            }
+
+            let (request_id, message) = self.request(
+                "accountSubscribe",
+                json!([
+                    account_pubkey.to_string(),
+                    { "encoding": "base64", "commitment": "confirmed" }
+                ]),
+            );
+            self.pending.insert(request_id, account_pubkey);
+            socket.send(message).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_message(
+        &mut self,
+        aggregator: &DataAggregator,
+        status: &SubscriptionStatus,
+        text: &str,
+    ) -> Result<(), Error> {
+        let message: RpcMessage = serde_json::from_str(text)?;
+
+        // Response to a subscribe request
+        if let Some(request_id) = message.id {
+            let account_pubkey = self.pending.remove(&request_id);
+            match (message.result, message.error, account_pubkey) {
+                (Some(subscription), None, Some(account_pubkey)) => {
+                    let subscription = subscription
+                        .as_u64()
+                        .ok_or_else(|| Error::msg("Subscription id is not a number."))?;
+                    self.subscriptions.insert(subscription, account_pubkey);
+                }
+                (_, Some(error), Some(account_pubkey)) => {
+                    // Retried on the next resubscribe tick
+                    self.subscribed.remove(&account_pubkey);
+                    warn!(account = %account_pubkey, %error, "Account subscription failed");
+                }
+                (_, Some(error), None) if Some(request_id) == self.slot_request_id => {
+                    warn!(%error, "Slot subscription failed");
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let Some(params) = message.params else {
+            return Ok(());
+        };
+
+        match message.method.as_deref() {
+            Some("accountNotification") => {
+                let Some(account_pubkey) = self.subscriptions.get(&params.subscription) else {
+                    return Ok(());
+                };
+                let notification: AccountNotification = serde_json::from_value(params.result)?;
+
+                let account = Account {
+                    account_pubkey: *account_pubkey,
+                    lamports: notification.value.lamports,
+                    owner: notification.value.owner.parse::<Pubkey>()?,
+                    executable: notification.value.executable,
+                    rent_epoch: notification.value.rent_epoch,
+                    fetched_at: unix_timestamp(),
+                    context_slot: notification.context.slot,
+                };
+                aggregator.retrieval.store_account(account).await?;
+            }
+            Some("slotNotification") => {
+                let notification: SlotNotification = serde_json::from_value(params.result)?;
+                status.slot.store(notification.slot, Ordering::SeqCst);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps all tracked accounts up to date with accountSubscribe notifications.
+/// The connection is re-established with backoff whenever it drops, until shutdown.
+pub async fn run_subscriptions(
+    aggregator: DataAggregator,
+    ws_url: String,
+    status: Arc<SubscriptionStatus>,
+    resubscribe_interval_in_sec: u64,
+    shutdown: CancellationToken,
+) -> Result<(), Error> {
+    let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+
+    loop {
+        let connection = tokio::select! {
+            connection = connect_async(ws_url.as_str()) => connection,
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+
+        match connection {
+            Ok((socket, _)) => {
+                reconnect_delay = INITIAL_RECONNECT_DELAY;
+                let result = run_session(
+                    &aggregator,
+                    socket,
+                    &status,
+                    resubscribe_interval_in_sec,
+                    &shutdown,
+                )
+                .await;
+                status.connected.store(false, Ordering::SeqCst);
+
+                if let Err(error) = result {
+                    warn!(
+                        error = format!("{:#}", error),
+                        "Subscription connection lost"
+                    );
+                }
+            }
+            Err(error) => warn!(%error, "Subscription connection failed"),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(reconnect_delay) => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn run_session(
+    aggregator: &DataAggregator,
+    mut socket: Socket,
+    status: &SubscriptionStatus,
+    resubscribe_interval_in_sec: u64,
+    shutdown: &CancellationToken,
+) -> Result<(), Error> {
+    let mut session = Session::default();
+    session.subscribe_slot(&mut socket).await?;
+
+    // Accounts fetched after the connection was opened are subscribed on the next tick
+    let mut resubscribe = interval(Duration::from_secs(resubscribe_interval_in_sec));
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                // Subscriptions end with the connection, a failed close is irrelevant here
+                _ = socket.close(None).await;
+                return Ok(());
+            }
+            _ = resubscribe.tick() => {
+                let account_pubkeys = aggregator.retrieval.get_tracked_accounts().await?;
+                session.subscribe_accounts(&mut socket, account_pubkeys).await?;
+                status.connected.store(true, Ordering::SeqCst);
+            }
+            message = socket.next() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Err(error) = session.handle_message(aggregator, status, &text).await {
+                            warn!(error = format!("{:#}", error), "Subscription message skipped");
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        return Err(Error::msg("Connection closed."));
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return Err(error.into()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod subscription_tests {
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use solana_sdk::pubkey::Pubkey;
+    use std::{
+        sync::{atomic::Ordering, Arc},
+        time::Duration,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::source::mock::MockSource;
+    use crate::subscription::{run_subscriptions, SubscriptionStatus};
+    use crate::types::{unix_timestamp, Account, DataAggregator, Retrieval};
+
+    async fn receive_request(socket: &mut WebSocketStream<TcpStream>) -> Value {
+        loop {
+            if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Accepts one connection, confirms the subscriptions and pushes a balance change
+    async fn serve_session(
+        listener: &TcpListener,
+        account_pubkey: Pubkey,
+        lamports: u64,
+    ) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = accept_async(stream).await.unwrap();
+
+        let slot_request = receive_request(&mut socket).await;
+        assert_eq!(slot_request["method"], "slotSubscribe");
+        let response = json!({"jsonrpc": "2.0", "result": 0, "id": slot_request["id"]});
+        socket
+            .send(Message::Text(response.to_string()))
+            .await
+            .unwrap();
+
+        let account_request = receive_request(&mut socket).await;
+        assert_eq!(account_request["method"], "accountSubscribe");
+        assert_eq!(account_request["params"][0], account_pubkey.to_string());
+        let response = json!({"jsonrpc": "2.0", "result": 7, "id": account_request["id"]});
+        socket
+            .send(Message::Text(response.to_string()))
+            .await
+            .unwrap();
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "accountNotification",
+            "params": {
+                "subscription": 7,
+                "result": {
+                    "context": { "slot": 100 },
+                    "value": {
+                        "lamports": lamports,
+                        "owner": "11111111111111111111111111111111",
+                        "executable": false,
+                        "rentEpoch": 18446744073709551615u64,
+                        "data": ["", "base64"],
+                        "space": 0
+                    }
+                }
+            }
+        });
+        socket
+            .send(Message::Text(notification.to_string()))
+            .await
+            .unwrap();
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "slotNotification",
+            "params": { "subscription": 0, "result": { "parent": 100, "root": 90, "slot": 101 } }
+        });
+        socket
+            .send(Message::Text(notification.to_string()))
+            .await
+            .unwrap();
+
+        socket
+    }
+
+    async fn wait_for_lamports(aggregator: &DataAggregator, account_pubkey: Pubkey, lamports: u64) {
+        for _ in 0..100 {
+            let account = aggregator
+                .retrieval
+                .get_account(account_pubkey.to_string())
+                .await
+                .unwrap();
+            if account.lamports == lamports {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Account was not updated to {} lamports.", lamports);
+    }
+
+    #[test]
+    fn report_each_slot_advance_once() {
+        let status = SubscriptionStatus::default();
+        assert!(!status.slot_advanced());
+
+        status.slot.store(101, Ordering::SeqCst);
+        assert!(status.slot_advanced());
+        assert!(!status.slot_advanced());
+    }
+
+    #[tokio::test]
+    async fn apply_notifications_and_resubscribe_after_reconnect() {
+        let source = Arc::new(MockSource::new());
+        let account_pubkey = Pubkey::new_unique();
+        source.set_account(Account {
+            account_pubkey,
+            lamports: 1,
+            owner: Pubkey::default(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            fetched_at: unix_timestamp(),
+            context_slot: 1,
+        });
+
+        let aggregator = DataAggregator::new(Retrieval::new(source));
+        aggregator
+            .retrieval
+            .fetch_account(account_pubkey.to_string())
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_url = format!("ws://{}", listener.local_addr().unwrap());
+        let status = Arc::new(SubscriptionStatus::default());
+        let shutdown = CancellationToken::new();
+        let subscriptions = tokio::spawn(run_subscriptions(
+            aggregator.clone(),
+            ws_url,
+            status.clone(),
+            60,
+            shutdown.clone(),
+        ));
+
+        let socket = serve_session(&listener, account_pubkey, 42).await;
+        wait_for_lamports(&aggregator, account_pubkey, 42).await;
+        assert!(status.is_connected());
+
+        // Drop the connection, the client should reconnect and resubscribe
+        drop(socket);
+        let _socket = serve_session(&listener, account_pubkey, 43).await;
+        wait_for_lamports(&aggregator, account_pubkey, 43).await;
+        assert_eq!(status.slot(), 101);
+
+        // Cancellation closes the session and ends the task
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), subscriptions)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(!status.is_connected());
+    }
+}
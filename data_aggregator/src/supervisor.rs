@@ -0,0 +1,203 @@
+use futures::FutureExt;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::metrics::METRICS;
+use crate::types::unix_timestamp;
+
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// State of a supervised task as served by /tasks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TaskStatus {
+    pub running: bool,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    // Unix timestamp of the last failure
+    pub last_error_at: Option<u64>,
+}
+
+/// Runs background tasks and restarts them with exponential backoff when they fail or panic.
+/// A task that ran for at least the maximum delay before failing restarts after the initial delay.
+pub struct Supervisor {
+    initial_delay: Duration,
+    max_delay: Duration,
+    tasks: Mutex<BTreeMap<String, TaskStatus>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new(INITIAL_RESTART_DELAY, MAX_RESTART_DELAY)
+    }
+}
+
+impl Supervisor {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Supervisor {
+            initial_delay,
+            max_delay,
+            tasks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Status of every supervised task by name
+    pub fn statuses(&self) -> BTreeMap<String, TaskStatus> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    fn update(&self, name: &str, update: impl FnOnce(&mut TaskStatus)) {
+        update(
+            self.tasks
+                .lock()
+                .unwrap()
+                .entry(name.to_string())
+                .or_default(),
+        );
+    }
+
+    /// Spawns the task and restarts it until it returns Ok or shutdown is cancelled.
+    /// Every run gets a fresh future from the factory.
+    pub fn spawn<F, Fut>(
+        self: &Arc<Self>,
+        name: impl Into<String>,
+        shutdown: CancellationToken,
+        task: F,
+    ) -> JoinHandle<Result<(), anyhow::Error>>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.into();
+        supervisor.update(&name, |status| status.running = true);
+
+        tokio::spawn(async move {
+            let mut delay = supervisor.initial_delay;
+
+            loop {
+                let started = Instant::now();
+                // A panic is handled like an error, the task is restarted
+                let error = match AssertUnwindSafe(task()).catch_unwind().await {
+                    Ok(Ok(())) => break,
+                    Ok(Err(error)) => format!("{:#}", error),
+                    Err(panic) => panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .map(|message| format!("Panicked: {}", message))
+                        .unwrap_or_else(|| "Panicked".to_string()),
+                };
+                if shutdown.is_cancelled() {
+                    break;
+                }
+
+                if started.elapsed() >= supervisor.max_delay {
+                    delay = supervisor.initial_delay;
+                }
+                error!(task = name.as_str(), %error, restart_in_ms = delay.as_millis() as u64, "Task failed");
+                supervisor.update(&name, |status| {
+                    status.restarts += 1;
+                    status.last_error = Some(error);
+                    status.last_error_at = Some(unix_timestamp());
+                });
+                METRICS.task_restarts.with_label_values(&[&name]).inc();
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => break,
+                }
+                delay = (delay * 2).min(supervisor.max_delay);
+                warn!(task = name.as_str(), "Task restarted");
+            }
+
+            supervisor.update(&name, |status| status.running = false);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use super::*;
+    use anyhow::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn restart_failed_and_panicked_task() {
+        let supervisor = Arc::new(Supervisor::new(
+            Duration::from_millis(10),
+            Duration::from_millis(40),
+        ));
+        let shutdown = CancellationToken::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let task_runs = runs.clone();
+        let task_shutdown = shutdown.clone();
+        let handle = supervisor.spawn("flaky", shutdown.clone(), move || {
+            let runs = task_runs.clone();
+            let shutdown = task_shutdown.clone();
+            async move {
+                match runs.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(Error::msg("upstream down")),
+                    1 => panic!("unexpected payload"),
+                    _ => {
+                        shutdown.cancelled().await;
+                        Ok(())
+                    }
+                }
+            }
+        });
+
+        while runs.load(Ordering::SeqCst) < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let status = supervisor.statuses()["flaky"].clone();
+        assert!(status.running);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(
+            status.last_error.as_deref(),
+            Some("Panicked: unexpected payload")
+        );
+        assert!(status.last_error_at.is_some());
+
+        shutdown.cancel();
+        handle.await.unwrap().unwrap();
+        assert!(!supervisor.statuses()["flaky"].running);
+    }
+
+    #[tokio::test]
+    async fn stop_restarting_on_shutdown() {
+        let supervisor = Arc::new(Supervisor::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        ));
+        let shutdown = CancellationToken::new();
+
+        let handle = supervisor.spawn("failing", shutdown.clone(), || async {
+            Err(Error::msg("storage unavailable"))
+        });
+
+        // The task waits for its restart delay, shutdown cuts it short
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let status = supervisor.statuses()["failing"].clone();
+        assert_eq!(status.restarts, 1);
+        assert!(!status.running);
+    }
+}
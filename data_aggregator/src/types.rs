@@ -2,44 +2,48 @@ use axum::{
     body::Body,
     http::{Response, StatusCode},
     response::IntoResponse,
+    Json,
 };
-use helius::Helius;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
-use std::{collections::HashMap, sync::Arc};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
-use tokio::sync::RwLock;
 
-/// DataAggregator can be shared between threads with read/write lock access
+use crate::retrieval::SingleFlight;
+use crate::source::ChainSource;
+use crate::storage::Storage;
+
+/// DataAggregator can be shared between threads without locking.
+/// Retrieval only takes &self, the storage synchronizes its own access.
 #[derive(Clone)]
 pub struct DataAggregator {
-    pub retrieval: Arc<RwLock<Retrieval>>,
+    pub retrieval: Arc<Retrieval>,
 }
 
 impl DataAggregator {
     pub fn new(retrieval: Retrieval) -> Self {
         Self {
-            retrieval: Arc::new(RwLock::new(retrieval)),
+            retrieval: Arc::new(retrieval),
         }
     }
 }
 
-// TODO: Helius and database can be abstracted in future to handle different
-// types of APIs and databases. Generics can be used here.
 pub struct Retrieval {
-    pub helius: Helius,
-    pub database: Database,
+    // Upstream provider of chain data, e.g. Helius
+    pub source: Arc<dyn ChainSource>,
+    // Cache backend for accounts and transactions, e.g. memory or SQLite
+    pub database: Box<dyn Storage>,
+    // In-flight upstream fetches by account id and signature, shared by concurrent misses
+    pub account_fetches: SingleFlight<Account>,
+    pub transaction_fetches: SingleFlight<Transaction>,
+    // Serve the cached account when the upstream fails to refresh it
+    pub serve_stale: bool,
+    // Slot the block crawler failed to process and the number of failed attempts
+    pub crawler_failures: Mutex<(u64, u32)>,
 }
 
-#[derive(Debug)]
-pub struct Database {
-    // Account's public key as a string is the hashmap key for account data
-    pub accounts: HashMap<String, Account>,
-    // The signature as a string serves as the hashmap key for transaction data
-    pub transactions: HashMap<String, Transaction>,
-}
-
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     // Account's public key
     pub account_pubkey: Pubkey,
@@ -51,51 +55,302 @@ pub struct Account {
     pub executable: bool,
     // The epoch at which this account will next owe rent
     pub rent_epoch: u64,
+    // Unix timestamp in seconds when the account was fetched from the upstream
+    #[serde(default)]
+    pub fetched_at: u64,
+    // Slot of the upstream response the account state was read at
+    #[serde(default)]
+    pub context_slot: u64,
 }
 
-// TODO: Add mappings to everything from EnhancedTransaction that is missing.
-// Especially account_data, instructions, events, token_transfers,
-// also read more about account_data and redesign the current code.
-#[derive(Debug, Clone, Serialize)]
+impl Account {
+    /// Seconds since the account was fetched from the upstream
+    pub fn age_secs(&self) -> u64 {
+        unix_timestamp().saturating_sub(self.fetched_at)
+    }
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Outcome of a tracked accounts refresh
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    pub updated: usize,
+    pub failed: Vec<AccountUpdateError>,
+}
+
+#[derive(Debug)]
+pub struct AccountUpdateError {
+    pub account_id: String,
+    pub error: String,
+}
+
+/// Outcome of a block crawler cycle
+#[derive(Debug, Default)]
+pub struct CrawlReport {
+    pub processed_slots: u64,
+    pub skipped_slots: u64,
+    // Slots given up on after their block failed repeatedly
+    pub failed_slots: Vec<u64>,
+    pub transactions: usize,
+    pub last_slot: Option<u64>,
+}
+
+/// Body of a batch transaction lookup
+#[derive(Debug, Deserialize)]
+pub struct TransactionsRequest {
+    pub signatures: Vec<String>,
+}
+
+/// Result of a single signature in a batch transaction lookup
+#[derive(Debug, Serialize)]
+pub struct TransactionLookup {
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<Transaction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// Freshness requirement of the account lookup
+#[derive(Debug, Deserialize)]
+pub struct AccountQuery {
+    // Cached accounts older than this many seconds are fetched again
+    pub max_age: Option<u64>,
+}
+
+/// Cursors of the account transaction history
+#[derive(Debug, Deserialize)]
+pub struct TransactionHistoryQuery {
+    pub before: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// A page of account transactions, newest first
+#[derive(Debug, Serialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    // Pass as `before` to get the next page, None on the last page
+    pub next_before: Option<String>,
+}
+
+// New fields have serde defaults, so transactions cached before they were added still load
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Transaction {
     pub signature: String,
     pub timestamp: u64,
     pub description: String,
+    // Helius transaction type, e.g. SWAP or NFT_SALE
+    #[serde(default)]
+    pub transaction_type: String,
+    // Program or marketplace that produced the transaction, e.g. JUPITER
+    #[serde(default)]
+    pub source: String,
     pub fee: i32,
     pub fee_payer: String,
     pub slot: i32,
+    #[serde(default)]
+    pub transaction_error: Option<String>,
     pub native_transfers: Option<Vec<NativeTransfer>>,
+    #[serde(default)]
+    pub token_transfers: Option<Vec<TokenTransfer>>,
+    // Native and token balance changes of every account touched by the transaction
+    #[serde(default)]
+    pub account_data: Vec<AccountBalanceChange>,
+    #[serde(default)]
+    pub instructions: Vec<Instruction>,
+    #[serde(default)]
+    pub events: TransactionEvents,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NativeTransfer {
     pub amount: u64,
     pub from_user_account: Option<String>,
     pub to_user_account: Option<String>,
 }
 
-// Define a custom error type
-#[derive(Error, Debug)]
-pub enum AppError {
-    #[error("Bad Request: {0}")]
-    BadRequest(String),
-    #[error("Internal Server Error: {0}")]
-    InternalServerError(String),
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenTransfer {
+    pub mint: String,
+    // Amount in token units, i.e. already divided by 10^decimals
+    pub token_amount: f64,
+    // Taken from the token balance changes, None when the mint is not among them
+    pub decimals: Option<u8>,
+    pub token_standard: String,
+    pub from_user_account: Option<String>,
+    pub to_user_account: Option<String>,
+    pub from_token_account: Option<String>,
+    pub to_token_account: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountBalanceChange {
+    pub account: String,
+    // Change of the SOL balance in lamports
+    pub native_balance_change: i64,
+    pub token_balance_changes: Vec<TokenBalanceChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenBalanceChange {
+    pub user_account: String,
+    pub token_account: String,
+    pub mint: String,
+    // Signed raw amount, the UI amount is raw_token_amount / 10^decimals
+    pub raw_token_amount: String,
+    pub decimals: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instruction {
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    pub data: String,
+    pub inner_instructions: Vec<InnerInstruction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InnerInstruction {
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransactionEvents {
+    pub swap: Option<SwapEvent>,
+    pub nft: Option<NftEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapEvent {
+    pub native_input: Option<NativeAmount>,
+    pub native_output: Option<NativeAmount>,
+    pub token_inputs: Vec<TokenBalanceChange>,
+    pub token_outputs: Vec<TokenBalanceChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NativeAmount {
+    pub account: String,
+    // Amount in lamports
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NftEvent {
+    pub event_type: String,
+    pub source: String,
+    pub description: String,
+    // Sale amount in lamports
+    pub amount: u64,
+    pub fee: u64,
+    pub sale_type: String,
+    pub buyer: String,
+    pub seller: String,
+    pub mints: Vec<String>,
+}
+
+/// Errors of the retrieval layer, each one has a stable code and an HTTP status
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RetrievalError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("Upstream unavailable: {0}")]
+    UpstreamUnavailable(String),
+    #[error("Upstream rate limit exceeded: {0}")]
+    RateLimited(String),
+    #[error("Upstream credit budget exhausted: {0}")]
+    BudgetExhausted(String),
+    #[error("Upstream request timed out: {0}")]
+    Timeout(String),
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("Upstream response cannot be decoded: {0}")]
+    Decode(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+impl RetrievalError {
+    /// Machine readable error code, stable across releases
+    pub fn code(&self) -> &'static str {
+        match self {
+            RetrievalError::NotFound(_) => "not_found",
+            RetrievalError::UpstreamUnavailable(_) => "upstream_unavailable",
+            RetrievalError::RateLimited(_) => "rate_limited",
+            RetrievalError::BudgetExhausted(_) => "budget_exhausted",
+            RetrievalError::Timeout(_) => "timeout",
+            RetrievalError::InvalidInput(_) => "invalid_input",
+            RetrievalError::Decode(_) => "decode_error",
+            RetrievalError::Storage(_) => "storage_error",
+            RetrievalError::Unsupported(_) => "unsupported",
+        }
+    }
+
+    /// Errors where the upstream did not answer and the same call may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            RetrievalError::UpstreamUnavailable(_)
+                | RetrievalError::Timeout(_)
+                | RetrievalError::RateLimited(_)
+        )
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RetrievalError::NotFound(_) => StatusCode::NOT_FOUND,
+            RetrievalError::UpstreamUnavailable(_) | RetrievalError::Decode(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            RetrievalError::RateLimited(_) | RetrievalError::BudgetExhausted(_) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            RetrievalError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            RetrievalError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            RetrievalError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            RetrievalError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        }
+    }
+}
+
+// Storage backends report anyhow errors
+impl From<anyhow::Error> for RetrievalError {
+    fn from(error: anyhow::Error) -> Self {
+        RetrievalError::Storage(format!("{:#}", error))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: ErrorBody,
 }
 
-// Implement IntoResponse for your custom error type
-impl IntoResponse for AppError {
+impl IntoResponse for RetrievalError {
     fn into_response(self) -> Response<Body> {
-        let (status, message) = match self {
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-        };
-
-        let body = format!("{{\"error\": \"{}\"}}", message);
-        Response::builder()
-            .status(status)
-            .header("Content-Type", "application/json")
-            .body(Body::from(body))
-            .unwrap()
+        (self.status(), Json(ErrorResponse { error: self.body() })).into_response()
     }
 }